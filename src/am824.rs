@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+
+/// AM824 subframes as used by SMPTE ST 2110-31 consist of a label byte followed by a 24 bit
+/// big endian audio sample. The label carries the AES3 bits: bit 5 marks the start of a channel
+/// status block, bit 4 the first subframe of a frame, then parity, channel status, user data
+/// and validity in bits 3 to 0.
+pub const SUBFRAME_BYTES: usize = 4;
+const VALIDITY: u8 = 0x01;
+const USER: u8 = 0x02;
+const CHANNEL_STATUS: u8 = 0x04;
+const BLOCK_START: u8 = 0x20;
+/// Frames per channel status block.
+const BLOCK_FRAMES: usize = 192;
+
+/// Strips the labels from AM824 subframes, leaving L24 audio.
+pub fn audio(payload: &[u8]) -> Vec<u8> {
+    payload
+        .chunks_exact(SUBFRAME_BYTES)
+        .flat_map(|s| [s[1], s[2], s[3]])
+        .collect()
+}
+
+/// Channel status of one channel as carried by a complete block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelStatus {
+    pub channel: u16,
+    /// Professional (AES3) rather than consumer (IEC 60958-3) channel status.
+    pub professional: bool,
+    /// Whether the channel carries linear PCM rather than e.g. compressed audio.
+    pub linear_pcm: bool,
+    pub sample_rate: Option<u32>,
+    /// Whether no sample of the block was flagged invalid.
+    pub valid: bool,
+    /// The 192 channel status bits, the first bit in the least significant bit of the first byte.
+    pub data: Vec<u8>,
+    /// The user bits of the block, in the same order.
+    pub user_data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Block {
+    /// Frame within the block, `None` until the start of a block was seen.
+    position: Option<usize>,
+    status: [u8; BLOCK_FRAMES / 8],
+    user: [u8; BLOCK_FRAMES / 8],
+    valid: bool,
+    last: Option<ChannelStatus>,
+}
+
+/// Collects the channel status, user and validity bits of AM824 payloads.
+pub struct ChannelStatusDecoder {
+    blocks: Vec<Block>,
+}
+
+impl ChannelStatusDecoder {
+    pub fn new(channels: u16) -> Self {
+        ChannelStatusDecoder {
+            blocks: vec![Block::default(); channels.max(1) as usize],
+        }
+    }
+
+    /// Returns the status of every channel that completed a block whose channel status or
+    /// validity differs from the previous block's. User data alone does not count as a change.
+    pub fn process(&mut self, payload: &[u8]) -> Vec<ChannelStatus> {
+        let mut changed = Vec::new();
+        let channels = self.blocks.len();
+
+        for (index, subframe) in payload.chunks_exact(SUBFRAME_BYTES).enumerate() {
+            let channel = index % channels;
+            let block = &mut self.blocks[channel];
+            let label = subframe[0];
+
+            if label & BLOCK_START != 0 {
+                block.position = Some(0);
+                block.status = Default::default();
+                block.user = Default::default();
+                block.valid = true;
+            }
+            let Some(position) = block.position else {
+                continue;
+            };
+
+            let (byte, bit) = (position / 8, position % 8);
+            if label & CHANNEL_STATUS != 0 {
+                block.status[byte] |= 1 << bit;
+            }
+            if label & USER != 0 {
+                block.user[byte] |= 1 << bit;
+            }
+            if label & VALIDITY != 0 {
+                block.valid = false;
+            }
+
+            if position + 1 < BLOCK_FRAMES {
+                block.position = Some(position + 1);
+                continue;
+            }
+            block.position = None;
+            let status = ChannelStatus {
+                channel: channel as u16,
+                professional: block.status[0] & 0x01 != 0,
+                linear_pcm: block.status[0] & 0x02 == 0,
+                sample_rate: sample_rate(&block.status),
+                valid: block.valid,
+                data: block.status.to_vec(),
+                user_data: block.user.to_vec(),
+            };
+            let unchanged = block
+                .last
+                .as_ref()
+                .is_some_and(|last| last.data == status.data && last.valid == status.valid);
+            block.last = Some(status.clone());
+            if !unchanged {
+                changed.push(status);
+            }
+        }
+
+        changed
+    }
+}
+
+fn sample_rate(status: &[u8]) -> Option<u32> {
+    if status[0] & 0x01 != 0 {
+        // AES3 byte 0, bits 6 and 7
+        match status[0] >> 6 {
+            0b10 => Some(48_000),
+            0b01 => Some(44_100),
+            0b11 => Some(32_000),
+            _ => None,
+        }
+    } else {
+        // IEC 60958-3 bits 24 to 27
+        match status[3] & 0x0F {
+            0b0000 => Some(44_100),
+            0b0010 => Some(48_000),
+            0b0011 => Some(32_000),
+            0b1000 => Some(88_200),
+            0b1010 => Some(96_000),
+            0b1100 => Some(176_400),
+            0b1110 => Some(192_000),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Two channels of `frames` frames, the channel status of a professional 48 kHz stream
+    /// repeating every block.
+    fn payload(start: usize, frames: usize, invalid: Option<usize>) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for frame in start..start + frames {
+            let position = frame % BLOCK_FRAMES;
+            for channel in 0..2 {
+                let mut label = 0;
+                if position == 0 {
+                    label |= BLOCK_START;
+                }
+                if channel == 0 {
+                    label |= 0x10;
+                }
+                // professional, 48 kHz
+                if [0, 7].contains(&position) {
+                    label |= CHANNEL_STATUS;
+                }
+                if invalid == Some(frame) && channel == 1 {
+                    label |= VALIDITY;
+                }
+                payload.extend_from_slice(&[label, 0x12, 0x34, channel]);
+            }
+        }
+        payload
+    }
+
+    #[test]
+    fn strip_labels() {
+        assert_eq!(
+            audio(&payload(0, 1, None)),
+            vec![0x12, 0x34, 0, 0x12, 0x34, 1]
+        );
+    }
+
+    #[test]
+    fn decode_channel_status() {
+        let mut decoder = ChannelStatusDecoder::new(2);
+        // joining mid-block, packets of 48 frames
+        let statuses: Vec<ChannelStatus> = (100..100 + 3 * BLOCK_FRAMES)
+            .step_by(48)
+            .flat_map(|start| decoder.process(&payload(start, 48, Some(300))))
+            .collect();
+
+        assert_eq!(statuses.len(), 3);
+        assert!(statuses.iter().all(|s| s.professional
+            && s.linear_pcm
+            && s.sample_rate == Some(48_000)
+            && s.data[0] == 0x81));
+        assert_eq!(
+            statuses
+                .iter()
+                .map(|s| (s.channel, s.valid))
+                .collect::<Vec<_>>(),
+            vec![(0, true), (1, false), (1, true)]
+        );
+    }
+}
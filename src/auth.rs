@@ -0,0 +1,348 @@
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use poem::Request;
+use ring::{constant_time, hmac};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::Ipv4Addr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::SessionDescriptor;
+
+/// Credentials accepted by the server. Static tokens and JWTs are sent in an
+/// `Authorization: Bearer` header or, as browsers cannot set headers on WebSocket connections,
+/// in a `token` query parameter.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub tokens: Vec<TokenConfig>,
+    /// Key for HMAC-SHA256 signed URLs, see [`sign_url`].
+    pub url_secret: Option<String>,
+    /// Key for JWTs signed with HS256. Their `sessions` and `groups` claims grant permissions
+    /// like those of static tokens, and they must expire.
+    pub jwt_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TokenConfig {
+    pub token: String,
+    #[serde(default)]
+    pub permissions: Permissions,
+}
+
+/// Sessions a client may play, record, package for HLS or transmit to. A session is permitted
+/// if either its name or its multicast group is listed. As clients describe the sessions they
+/// request themselves, names are only trusted from configured or announced sessions, permitting
+/// their multicast groups.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Permissions {
+    /// Session names, `*` permitting all sessions.
+    pub sessions: Vec<String>,
+    pub groups: Vec<Ipv4Addr>,
+}
+
+impl Permissions {
+    pub fn all() -> Self {
+        Permissions {
+            sessions: vec!["*".to_owned()],
+            groups: Vec::new(),
+        }
+    }
+
+    /// Whether the session may be accessed, resolving session names against `known`.
+    pub fn allows(&self, descriptor: &SessionDescriptor, known: &[SessionDescriptor]) -> bool {
+        let group = descriptor.multicast_address;
+        self.sessions.iter().any(|s| s == "*")
+            || self.groups.contains(&group)
+            || known.iter().any(|k| {
+                k.multicast_address == group
+                    && k.session_name
+                        .as_ref()
+                        .is_some_and(|name| self.sessions.contains(name))
+            })
+    }
+
+    pub fn check(
+        &self,
+        descriptor: &SessionDescriptor,
+        known: &[SessionDescriptor],
+    ) -> anyhow::Result<()> {
+        if self.allows(descriptor, known) {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "not permitted to access session {} on {}",
+                descriptor.session_name.as_deref().unwrap_or("-"),
+                descriptor.multicast_address
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct QueryCredentials {
+    token: Option<String>,
+    expires: Option<u64>,
+    sessions: Option<String>,
+    groups: Option<String>,
+    signature: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    exp: Option<u64>,
+    #[serde(default)]
+    sessions: Vec<String>,
+    #[serde(default)]
+    groups: Vec<Ipv4Addr>,
+}
+
+/// Checks the credentials of requests, permitting everything if authentication is disabled.
+#[derive(Debug, Clone, Default)]
+pub struct Authenticator {
+    config: Option<Arc<AuthConfig>>,
+}
+
+impl Authenticator {
+    pub fn new(config: Option<AuthConfig>) -> Self {
+        Authenticator {
+            config: config.map(Arc::new),
+        }
+    }
+
+    pub fn authenticate(&self, request: &Request) -> anyhow::Result<Permissions> {
+        let Some(config) = &self.config else {
+            return Ok(Permissions::all());
+        };
+        let query: QueryCredentials = request.params().context("invalid credentials")?;
+
+        let bearer = request
+            .header("authorization")
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .or(query.token.as_deref());
+        if let Some(token) = bearer {
+            let known = config.tokens.iter().find(|t| {
+                constant_time::verify_slices_are_equal(t.token.as_bytes(), token.as_bytes()).is_ok()
+            });
+            return match (known, &config.jwt_secret) {
+                (Some(known), _) => Ok(known.permissions.clone()),
+                (None, Some(secret)) if token.contains('.') => verify_jwt(secret, token),
+                (None, _) => Err(anyhow!("invalid token")),
+            };
+        }
+
+        if let Some(signature) = &query.signature {
+            let secret = config
+                .url_secret
+                .as_ref()
+                .ok_or_else(|| anyhow!("signed URLs are not accepted"))?;
+            let signed = signed_part(request.uri().path(), request.uri().query().unwrap_or(""));
+            verify(secret, &signed, signature)?;
+            check_expiry(
+                query
+                    .expires
+                    .ok_or_else(|| anyhow!("signed URL never expires"))?,
+            )?;
+            let groups = split(&query.groups)
+                .map(|g| g.parse().with_context(|| format!("invalid group {g}")))
+                .collect::<anyhow::Result<_>>()?;
+            return Ok(Permissions {
+                sessions: split(&query.sessions).map(str::to_owned).collect(),
+                groups,
+            });
+        }
+
+        Err(anyhow!("missing credentials"))
+    }
+}
+
+/// Signs a path and query, which should contain an `expires` parameter in seconds since the
+/// Unix epoch and the comma separated `sessions` and/or `groups` to permit. The signature covers
+/// the query exactly as given, so values must already be percent encoded.
+pub fn sign_url(secret: &str, path: &str, query: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let signature = hmac::sign(&key, signed_part(path, query).as_bytes());
+    format!(
+        "{path}?{query}&signature={}",
+        URL_SAFE_NO_PAD.encode(signature)
+    )
+}
+
+/// The path and the query without the signature. Signatures of HLS files cover the directory of
+/// their packager, so that a signed playlist URL also grants access to its segments.
+fn signed_part(path: &str, query: &str) -> String {
+    let path = match path.rsplit_once('/') {
+        Some((directory, _)) if directory.starts_with("/hls/") => &path[..=directory.len()],
+        _ => path,
+    };
+    let query: Vec<&str> = query
+        .split('&')
+        .filter(|p| !p.is_empty() && !p.starts_with("signature="))
+        .collect();
+    format!("{path}?{}", query.join("&"))
+}
+
+fn split(list: &Option<String>) -> impl Iterator<Item = &str> {
+    list.iter()
+        .flat_map(|l| l.split(','))
+        .filter(|s| !s.is_empty())
+}
+
+fn verify(secret: &str, message: &str, signature: &str) -> anyhow::Result<()> {
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| anyhow!("invalid signature"))?;
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::verify(&key, message.as_bytes(), &signature).map_err(|_| anyhow!("invalid signature"))
+}
+
+fn check_expiry(expires: u64) -> anyhow::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if now < expires {
+        Ok(())
+    } else {
+        Err(anyhow!("credentials expired"))
+    }
+}
+
+fn verify_jwt(secret: &str, token: &str) -> anyhow::Result<Permissions> {
+    let (signed, signature) = token
+        .rsplit_once('.')
+        .ok_or_else(|| anyhow!("invalid JWT"))?;
+    let (header, claims) = signed
+        .split_once('.')
+        .ok_or_else(|| anyhow!("invalid JWT"))?;
+    let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).context("invalid JWT");
+
+    let header: JwtHeader = serde_json::from_slice(&decode(header)?).context("invalid JWT")?;
+    // only the algorithm configured here may be used, whatever the token announces
+    if header.alg != "HS256" {
+        return Err(anyhow!("unsupported JWT algorithm {}", header.alg));
+    }
+    verify(secret, signed, signature)?;
+    let claims: JwtClaims = serde_json::from_slice(&decode(claims)?).context("invalid JWT")?;
+    check_expiry(claims.exp.ok_or_else(|| anyhow!("JWT never expires"))?)?;
+    Ok(Permissions {
+        sessions: claims.sessions,
+        groups: claims.groups,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use poem::http::Uri;
+
+    fn request(uri: &str, authorization: Option<&str>) -> Request {
+        let mut request = Request::builder().uri(uri.parse::<Uri>().unwrap());
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        request.finish()
+    }
+
+    fn descriptor(session_name: &str, group: Ipv4Addr) -> SessionDescriptor {
+        let mut descriptor: SessionDescriptor = "v=0\r\n\
+            o=- 1 1 IN IP4 192.168.1.20\r\n\
+            s=Stage\r\n\
+            c=IN IP4 239.69.3.4/32\r\n\
+            t=0 0\r\n\
+            m=audio 5004 RTP/AVP 97\r\n\
+            a=rtpmap:97 L24/48000/2\r\n\
+            a=ptime:1\r\n"
+            .parse()
+            .unwrap();
+        descriptor.session_name = Some(session_name.to_owned());
+        descriptor.multicast_address = group;
+        descriptor
+    }
+
+    fn jwt(secret: &str, claims: &str) -> String {
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#),
+            URL_SAFE_NO_PAD.encode(claims)
+        );
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(hmac::sign(&key, signed.as_bytes()));
+        format!("{signed}.{signature}")
+    }
+
+    #[test]
+    fn authenticate() {
+        let stage = Ipv4Addr::new(239, 69, 3, 4);
+        let studio = Ipv4Addr::new(239, 69, 3, 5);
+        let authenticator = Authenticator::new(Some(AuthConfig {
+            tokens: vec![TokenConfig {
+                token: "secret".to_owned(),
+                permissions: Permissions {
+                    sessions: vec!["Stage".to_owned()],
+                    groups: Vec::new(),
+                },
+            }],
+            url_secret: Some("url key".to_owned()),
+            jwt_secret: Some("jwt key".to_owned()),
+        }));
+
+        let permissions = authenticator
+            .authenticate(&request("/ws", Some("Bearer secret")))
+            .unwrap();
+        let known = [descriptor("Stage", stage)];
+        assert!(permissions.allows(&descriptor("Studio", stage), &known));
+        assert!(!permissions.allows(&descriptor("Stage", studio), &known));
+        assert!(!permissions.allows(&descriptor("Stage", stage), &[]));
+        assert!(authenticator
+            .authenticate(&request("/ws?token=secret", None))
+            .is_ok());
+        assert!(authenticator
+            .authenticate(&request("/ws", Some("Bearer wrong")))
+            .is_err());
+        assert!(authenticator.authenticate(&request("/ws", None)).is_err());
+
+        let url = sign_url("url key", "/ws", "expires=4000000000&groups=239.69.3.4");
+        let permissions = authenticator.authenticate(&request(&url, None)).unwrap();
+        assert!(permissions.allows(&descriptor("Studio", stage), &[]));
+        let tampered = url.replace("239.69.3.4", "239.69.3.5");
+        assert!(authenticator
+            .authenticate(&request(&tampered, None))
+            .is_err());
+        let expired = sign_url("url key", "/ws", "expires=1000&groups=239.69.3.4");
+        assert!(authenticator
+            .authenticate(&request(&expired, None))
+            .is_err());
+
+        let token = jwt("jwt key", r#"{"exp":4000000000,"sessions":["*"]}"#);
+        let permissions = authenticator
+            .authenticate(&request("/ws", Some(&format!("Bearer {token}"))))
+            .unwrap();
+        assert!(permissions.allows(&descriptor("Studio", stage), &[]));
+        for token in [
+            jwt("other key", r#"{"exp":4000000000,"sessions":["*"]}"#),
+            jwt("jwt key", r#"{"exp":1000,"sessions":["*"]}"#),
+            jwt("jwt key", r#"{"sessions":["*"]}"#),
+        ] {
+            assert!(authenticator
+                .authenticate(&request(&format!("/ws?token={token}"), None))
+                .is_err());
+        }
+
+        assert_eq!(
+            Authenticator::default()
+                .authenticate(&request("/ws", None))
+                .unwrap(),
+            Permissions::all()
+        );
+    }
+}
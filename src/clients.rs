@@ -0,0 +1,308 @@
+use poem::web::websocket::CloseCode;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+
+use crate::{auth::Permissions, config::LimitConfig};
+
+/// A limit a client ran into.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitExceeded {
+    Clients { max: usize },
+    ClientsPerAddress { address: IpAddr, max: usize },
+    Streams { max: usize },
+    Bitrate { max: u64 },
+}
+
+impl LimitExceeded {
+    /// Close code for connections refused because of this limit.
+    pub fn close_code(&self) -> CloseCode {
+        match self {
+            // 1013 Try Again Later
+            LimitExceeded::Clients { .. } | LimitExceeded::Bitrate { .. } => CloseCode::Again,
+            LimitExceeded::ClientsPerAddress { .. } | LimitExceeded::Streams { .. } => {
+                CloseCode::Policy
+            }
+        }
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Clients { max } => write!(f, "server is full ({max} clients)"),
+            LimitExceeded::ClientsPerAddress { address, max } => {
+                write!(f, "too many clients from {address} (at most {max})")
+            }
+            LimitExceeded::Streams { max } => {
+                write!(f, "too many streams on this connection (at most {max})")
+            }
+            LimitExceeded::Bitrate { max } => {
+                write!(f, "outgoing bandwidth of {max} bit/s exhausted")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Why a client went away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DisconnectReason {
+    /// The client closed the connection.
+    Closed,
+    /// Reading from or writing to the connection failed.
+    Failed,
+    /// The client answered no ping within the keepalive timeout.
+    TimedOut,
+    /// The server shut down.
+    ShutDown,
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DisconnectReason::Closed => "closed by the client",
+            DisconnectReason::Failed => "connection failed",
+            DisconnectReason::TimedOut => "keepalive timed out",
+            DisconnectReason::ShutDown => "server shut down",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientStats {
+    pub connected: usize,
+    /// Number of clients that went away since startup, by reason.
+    pub disconnects: BTreeMap<DisconnectReason, u64>,
+}
+
+#[derive(Default)]
+struct Usage {
+    clients: usize,
+    clients_per_address: HashMap<IpAddr, usize>,
+    /// Bits per second reserved by the streams of all clients.
+    bitrate: u64,
+    disconnects: BTreeMap<DisconnectReason, u64>,
+}
+
+/// Counts the connected WebSocket clients and the outgoing bandwidth of their streams.
+#[derive(Clone, Default)]
+pub struct ClientRegistry {
+    limits: LimitConfig,
+    usage: Arc<Mutex<Usage>>,
+    disconnects: Arc<Notify>,
+}
+
+impl ClientRegistry {
+    pub fn new(limits: &LimitConfig) -> Self {
+        ClientRegistry {
+            limits: limits.clone(),
+            usage: Default::default(),
+            disconnects: Default::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ClientStats {
+        let usage = self.usage.lock().expect("mutex poisoned");
+        ClientStats {
+            connected: usage.clients,
+            disconnects: usage.disconnects.clone(),
+        }
+    }
+
+    /// Waits until no client is connected.
+    pub async fn disconnected(&self) {
+        loop {
+            let disconnect = self.disconnects.notified();
+            if self.usage.lock().expect("mutex poisoned").clients == 0 {
+                return;
+            }
+            disconnect.await;
+        }
+    }
+
+    /// Registers a client until the returned handle is dropped.
+    pub fn connect(
+        &self,
+        address: Option<IpAddr>,
+        permissions: Permissions,
+    ) -> Result<Client, LimitExceeded> {
+        let mut usage = self.usage.lock().expect("mutex poisoned");
+        if let Some(max) = self.limits.max_clients {
+            if usage.clients >= max {
+                return Err(LimitExceeded::Clients { max });
+            }
+        }
+        if let (Some(address), Some(max)) = (address, self.limits.max_clients_per_address) {
+            if usage
+                .clients_per_address
+                .get(&address)
+                .copied()
+                .unwrap_or(0)
+                >= max
+            {
+                return Err(LimitExceeded::ClientsPerAddress { address, max });
+            }
+        }
+        usage.clients += 1;
+        if let Some(address) = address {
+            *usage.clients_per_address.entry(address).or_default() += 1;
+        }
+        Ok(Client {
+            registry: self.clone(),
+            address,
+            permissions,
+            streams: Default::default(),
+            reason: None,
+        })
+    }
+}
+
+/// A connected client, unregistered when dropped.
+pub struct Client {
+    registry: ClientRegistry,
+    pub address: Option<IpAddr>,
+    pub permissions: Permissions,
+    streams: Arc<Mutex<usize>>,
+    reason: Option<DisconnectReason>,
+}
+
+impl Client {
+    /// Unregisters the client, counting why it went away.
+    pub fn disconnect(mut self, reason: DisconnectReason) {
+        self.reason = Some(reason);
+    }
+
+    /// Reserves a stream of the given outgoing bitrate until the returned slot is dropped.
+    pub fn start_stream(&self, bitrate: u64) -> Result<StreamSlot, LimitExceeded> {
+        self.replace_stream(None, bitrate)
+    }
+
+    /// Reserves a stream replacing another one, which is not counted against the limits as it
+    /// is about to be released.
+    pub fn replace_stream(
+        &self,
+        replaced: Option<&StreamSlot>,
+        bitrate: u64,
+    ) -> Result<StreamSlot, LimitExceeded> {
+        let limits = &self.registry.limits;
+        let (replaced_streams, replaced_bitrate) = replaced.map_or((0, 0), |s| (1, s.bitrate));
+        let mut streams = self.streams.lock().expect("mutex poisoned");
+        if let Some(max) = limits.max_streams_per_client {
+            if *streams - replaced_streams >= max {
+                return Err(LimitExceeded::Streams { max });
+            }
+        }
+        let mut usage = self.registry.usage.lock().expect("mutex poisoned");
+        if let Some(max) = limits.max_outgoing_bitrate {
+            if usage.bitrate - replaced_bitrate + bitrate > max {
+                return Err(LimitExceeded::Bitrate { max });
+            }
+        }
+        usage.bitrate += bitrate;
+        *streams += 1;
+        Ok(StreamSlot {
+            registry: self.registry.clone(),
+            streams: self.streams.clone(),
+            bitrate,
+        })
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let mut usage = self.registry.usage.lock().expect("mutex poisoned");
+        usage.clients -= 1;
+        let reason = self.reason.unwrap_or(DisconnectReason::Failed);
+        *usage.disconnects.entry(reason).or_default() += 1;
+        if let Some(address) = self.address {
+            if let Some(count) = usage.clients_per_address.get_mut(&address) {
+                *count -= 1;
+                if *count == 0 {
+                    usage.clients_per_address.remove(&address);
+                }
+            }
+        }
+        self.registry.disconnects.notify_waiters();
+    }
+}
+
+/// A stream counted against the limits of its client, released when dropped.
+pub struct StreamSlot {
+    registry: ClientRegistry,
+    streams: Arc<Mutex<usize>>,
+    bitrate: u64,
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        *self.streams.lock().expect("mutex poisoned") -= 1;
+        self.registry.usage.lock().expect("mutex poisoned").bitrate -= self.bitrate;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn enforce_limits() {
+        let registry = ClientRegistry::new(&LimitConfig {
+            max_clients: Some(3),
+            max_clients_per_address: Some(2),
+            max_streams_per_client: Some(2),
+            max_outgoing_bitrate: Some(3_000_000),
+            ..Default::default()
+        });
+        let a = IpAddr::from([10, 0, 0, 1]);
+        let b = IpAddr::from([10, 0, 0, 2]);
+
+        let first = registry.connect(Some(a), Permissions::all()).unwrap();
+        let second = registry.connect(Some(a), Permissions::all()).unwrap();
+        assert_eq!(
+            registry.connect(Some(a), Permissions::all()).err(),
+            Some(LimitExceeded::ClientsPerAddress { address: a, max: 2 })
+        );
+        let third = registry.connect(Some(b), Permissions::all()).unwrap();
+        assert_eq!(
+            registry.connect(Some(b), Permissions::all()).err(),
+            Some(LimitExceeded::Clients { max: 3 })
+        );
+        second.disconnect(DisconnectReason::TimedOut);
+        let _fourth = registry.connect(Some(a), Permissions::all()).unwrap();
+
+        let stream = first.start_stream(1_000_000).unwrap();
+        let _other = first.start_stream(1_000_000).unwrap();
+        assert_eq!(
+            first.start_stream(0).err(),
+            Some(LimitExceeded::Streams { max: 2 })
+        );
+        assert_eq!(
+            third.start_stream(1_500_000).err(),
+            Some(LimitExceeded::Bitrate { max: 3_000_000 })
+        );
+        let replacement = first.replace_stream(Some(&stream), 1_000_000).unwrap();
+        drop(stream);
+        drop(replacement);
+        assert!(third.start_stream(1_500_000).is_ok());
+
+        drop(third);
+        let stats = registry.stats();
+        assert_eq!(stats.connected, 2);
+        assert_eq!(
+            stats.disconnects,
+            BTreeMap::from([
+                (DisconnectReason::Failed, 1),
+                (DisconnectReason::TimedOut, 1)
+            ])
+        );
+    }
+}
@@ -0,0 +1,515 @@
+use crate::{
+    auth::AuthConfig,
+    hls::HlsConfig,
+    poem::Session,
+    policy::PolicyConfig,
+    recorder::RecorderConfig,
+    tls::{self, TlsConfig},
+    transmitter,
+};
+use anyhow::{anyhow, Context};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+};
+
+/// Configuration file read if no other file is given.
+pub const DEFAULT_CONFIG_FILE: &str = "aes67-to-ws.toml";
+
+/// Server configuration, layered from defaults, a TOML file, environment variables (also read
+/// from `.env`) and command line flags, each overriding the previous. Keys of the file are
+/// camelCase throughout, like those of the JSON API whose sessions and recording and HLS
+/// settings it shares, e.g. `shutdownTimeout` and `record = { maxDuration = 3600 }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// Accept TLS connections, on the main port or an additional one.
+    pub tls: Option<TlsConfig>,
+    /// Require clients to authenticate. Without it, anyone may play any session.
+    pub auth: Option<AuthConfig>,
+    /// Multicast sessions clients may receive or transmit.
+    pub policy: PolicyConfig,
+    pub interfaces: InterfaceConfig,
+    pub buffers: BufferConfig,
+    pub limits: LimitConfig,
+    pub keepalive: KeepaliveConfig,
+    /// Directory recordings are written to.
+    pub recordings_directory: PathBuf,
+    /// Directory capture sessions are read from.
+    pub captures_directory: PathBuf,
+    /// Send RTCP receiver reports to the senders of played streams.
+    pub rtcp_receiver_reports: bool,
+    /// Sessions recorded or packaged for HLS from startup on.
+    pub sessions: Vec<StaticSession>,
+    /// Seconds to wait on SIGTERM or Ctrl-C for clients to disconnect and recordings to be
+    /// finalized before exiting.
+    pub shutdown_timeout: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: Ipv4Addr::UNSPECIFIED.into(),
+            port: 9999,
+            tls: None,
+            auth: None,
+            policy: Default::default(),
+            interfaces: Default::default(),
+            buffers: Default::default(),
+            limits: Default::default(),
+            keepalive: Default::default(),
+            recordings_directory: "recordings".into(),
+            captures_directory: "captures".into(),
+            rtcp_receiver_reports: false,
+            sessions: Vec::new(),
+            shutdown_timeout: 10,
+        }
+    }
+}
+
+/// Local addresses of the network interfaces used, unspecified to let the OS choose.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct InterfaceConfig {
+    /// Interface multicast groups of received streams are joined on.
+    pub receive: Ipv4Addr,
+    /// Interface transmitted streams are sent from.
+    pub transmit: Ipv4Addr,
+    /// Interface PTP messages are received on.
+    pub ptp: Ipv4Addr,
+}
+
+impl Default for InterfaceConfig {
+    fn default() -> Self {
+        InterfaceConfig {
+            receive: Ipv4Addr::UNSPECIFIED,
+            transmit: Ipv4Addr::UNSPECIFIED,
+            ptp: Ipv4Addr::UNSPECIFIED,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct BufferConfig {
+    /// Receive buffer size of multicast sockets in bytes, the OS default if unset.
+    pub socket_receive_buffer: Option<usize>,
+    /// Seconds of audio buffered for transmitted streams before the oldest audio is dropped.
+    pub transmit_buffer_seconds: f64,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        BufferConfig {
+            socket_receive_buffer: None,
+            transmit_buffer_seconds: transmitter::DEFAULT_BUFFERED_SECONDS,
+        }
+    }
+}
+
+/// Pings sent to WebSocket clients to detect connections that died without being closed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct KeepaliveConfig {
+    /// Seconds between pings, 0 to send none and never time out.
+    pub interval: u64,
+    /// Seconds without any message from a client, pongs included, after which it is
+    /// disconnected.
+    pub timeout: u64,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        KeepaliveConfig {
+            interval: 15,
+            timeout: 45,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct LimitConfig {
+    /// Maximum number of concurrently running recordings.
+    pub max_recordings: Option<usize>,
+    /// Maximum number of concurrently running HLS packagers.
+    pub max_hls_streams: Option<usize>,
+    /// Maximum number of connected WebSocket clients.
+    pub max_clients: Option<usize>,
+    /// Maximum number of WebSocket clients connected from the same IP address.
+    pub max_clients_per_address: Option<usize>,
+    /// Maximum number of streams played concurrently by a WebSocket client.
+    pub max_streams_per_client: Option<usize>,
+    /// Maximum total bitrate of the audio sent to WebSocket clients, in bits per second.
+    pub max_outgoing_bitrate: Option<u64>,
+}
+
+/// A session recorded and/or packaged for HLS under a fixed name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct StaticSession {
+    pub name: String,
+    pub session: Session,
+    #[serde(default)]
+    pub record: Option<RecorderConfig>,
+    #[serde(default)]
+    pub hls: Option<HlsConfig>,
+}
+
+/// Forwards AES67 streams to WebSocket clients. All flags can also be set through the
+/// environment.
+#[derive(Debug, Default, Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// Configuration file [default: aes67-to-ws.toml, if it exists]
+    #[arg(short, long, env = "AES67_TO_WS_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "AES67_TO_WS_BIND_ADDRESS")]
+    pub bind_address: Option<IpAddr>,
+    #[arg(short, long, env = "AES67_TO_WS_PORT")]
+    pub port: Option<u16>,
+    /// PEM encoded TLS certificate chain
+    #[arg(long, env = "AES67_TO_WS_TLS_CERTIFICATE")]
+    pub tls_certificate: Option<PathBuf>,
+    /// PEM encoded TLS private key
+    #[arg(long, env = "AES67_TO_WS_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    /// Serve TLS on this port and plain connections on the main port
+    #[arg(long, env = "AES67_TO_WS_TLS_PORT")]
+    pub tls_port: Option<u16>,
+    /// Local address of the interface used for all multicast traffic
+    #[arg(long, env = "AES67_TO_WS_INTERFACE")]
+    pub interface: Option<Ipv4Addr>,
+    #[arg(long, env = "AES67_TO_WS_RECORDINGS_DIRECTORY")]
+    pub recordings_directory: Option<PathBuf>,
+    #[arg(long, env = "AES67_TO_WS_CAPTURES_DIRECTORY")]
+    pub captures_directory: Option<PathBuf>,
+    #[arg(long, env = "AES67_TO_WS_RTCP_RECEIVER_REPORTS")]
+    pub rtcp_receiver_reports: Option<bool>,
+    #[arg(long, env = "AES67_TO_WS_SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: Option<u64>,
+    /// Receive buffer size of multicast sockets in bytes
+    #[arg(long, env = "AES67_TO_WS_SOCKET_RECEIVE_BUFFER")]
+    pub socket_receive_buffer: Option<usize>,
+    /// Seconds of audio buffered for transmitted streams
+    #[arg(long, env = "AES67_TO_WS_TRANSMIT_BUFFER_SECONDS")]
+    pub transmit_buffer_seconds: Option<f64>,
+    #[arg(long, env = "AES67_TO_WS_MAX_RECORDINGS")]
+    pub max_recordings: Option<usize>,
+    #[arg(long, env = "AES67_TO_WS_MAX_HLS_STREAMS")]
+    pub max_hls_streams: Option<usize>,
+    #[arg(long, env = "AES67_TO_WS_MAX_CLIENTS")]
+    pub max_clients: Option<usize>,
+    #[arg(long, env = "AES67_TO_WS_MAX_CLIENTS_PER_ADDRESS")]
+    pub max_clients_per_address: Option<usize>,
+    #[arg(long, env = "AES67_TO_WS_MAX_STREAMS_PER_CLIENT")]
+    pub max_streams_per_client: Option<usize>,
+    /// Maximum total bitrate of the audio sent to clients in bits per second
+    #[arg(long, env = "AES67_TO_WS_MAX_OUTGOING_BITRATE")]
+    pub max_outgoing_bitrate: Option<u64>,
+    /// Seconds between pings sent to WebSocket clients, 0 to send none
+    #[arg(long, env = "AES67_TO_WS_KEEPALIVE_INTERVAL")]
+    pub keepalive_interval: Option<u64>,
+    /// Seconds without any message after which a WebSocket client is disconnected
+    #[arg(long, env = "AES67_TO_WS_KEEPALIVE_TIMEOUT")]
+    pub keepalive_timeout: Option<u64>,
+}
+
+impl Config {
+    /// Loads the configuration from all layers and validates it. Variables from `.env` must
+    /// have been loaded into the environment before.
+    pub fn load() -> anyhow::Result<Self> {
+        Self::from_args(Args::parse())
+    }
+
+    pub fn from_args(args: Args) -> anyhow::Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if fs::metadata(DEFAULT_CONFIG_FILE).is_ok() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Config::default(),
+        };
+
+        if let Some(bind_address) = args.bind_address {
+            config.bind_address = bind_address;
+        }
+        if let Some(port) = args.port {
+            config.port = port;
+        }
+        if args.tls_certificate.is_some() || args.tls_key.is_some() || args.tls_port.is_some() {
+            let tls = config.tls.take();
+            let certificate = args
+                .tls_certificate
+                .or_else(|| tls.as_ref().map(|t| t.certificate.clone()))
+                .ok_or_else(|| anyhow!("invalid configuration: TLS requires a certificate"))?;
+            let key = args
+                .tls_key
+                .or_else(|| tls.as_ref().map(|t| t.key.clone()))
+                .ok_or_else(|| anyhow!("invalid configuration: TLS requires a key"))?;
+            config.tls = Some(TlsConfig {
+                certificate,
+                key,
+                port: args.tls_port.or(tls.as_ref().and_then(|t| t.port)),
+                reload_interval: tls
+                    .map_or_else(tls::default_reload_interval, |t| t.reload_interval),
+            });
+        }
+        if let Some(interface) = args.interface {
+            config.interfaces = InterfaceConfig {
+                receive: interface,
+                transmit: interface,
+                ptp: interface,
+            };
+        }
+        if let Some(directory) = args.recordings_directory {
+            config.recordings_directory = directory;
+        }
+        if let Some(directory) = args.captures_directory {
+            config.captures_directory = directory;
+        }
+        if let Some(receiver_reports) = args.rtcp_receiver_reports {
+            config.rtcp_receiver_reports = receiver_reports;
+        }
+        if let Some(timeout) = args.shutdown_timeout {
+            config.shutdown_timeout = timeout;
+        }
+        if let Some(size) = args.socket_receive_buffer {
+            config.buffers.socket_receive_buffer = Some(size);
+        }
+        if let Some(seconds) = args.transmit_buffer_seconds {
+            config.buffers.transmit_buffer_seconds = seconds;
+        }
+        let limits = &mut config.limits;
+        limits.max_recordings = args.max_recordings.or(limits.max_recordings);
+        limits.max_hls_streams = args.max_hls_streams.or(limits.max_hls_streams);
+        limits.max_clients = args.max_clients.or(limits.max_clients);
+        limits.max_clients_per_address = args
+            .max_clients_per_address
+            .or(limits.max_clients_per_address);
+        limits.max_streams_per_client = args
+            .max_streams_per_client
+            .or(limits.max_streams_per_client);
+        limits.max_outgoing_bitrate = args.max_outgoing_bitrate.or(limits.max_outgoing_bitrate);
+        if let Some(interval) = args.keepalive_interval {
+            config.keepalive.interval = interval;
+        }
+        if let Some(timeout) = args.keepalive_timeout {
+            config.keepalive.timeout = timeout;
+        }
+
+        config.validate().context("invalid configuration")?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let toml = fs::read_to_string(path)
+            .with_context(|| format!("cannot read configuration file {}", path.display()))?;
+        toml::from_str(&toml)
+            .with_context(|| format!("invalid configuration file {}", path.display()))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(tls) = &self.tls {
+            if tls.port == Some(self.port) {
+                return Err(anyhow!("TLS port {} is also the plain port", self.port));
+            }
+        }
+        if let Some(auth) = &self.auth {
+            if auth.tokens.is_empty() && auth.url_secret.is_none() && auth.jwt_secret.is_none() {
+                return Err(anyhow!("authentication accepts no credentials"));
+            }
+            let secrets = [&auth.url_secret, &auth.jwt_secret];
+            if auth.tokens.iter().any(|t| t.token.is_empty())
+                || secrets
+                    .iter()
+                    .any(|s| s.as_ref().is_some_and(String::is_empty))
+            {
+                return Err(anyhow!(
+                    "authentication tokens and secrets must not be empty"
+                ));
+            }
+        }
+        let interfaces = [
+            ("receive", self.interfaces.receive),
+            ("transmit", self.interfaces.transmit),
+            ("ptp", self.interfaces.ptp),
+        ];
+        for (name, address) in interfaces {
+            if address.is_multicast() || address.is_broadcast() {
+                return Err(anyhow!("{name} interface {address} is not a local address"));
+            }
+        }
+        if self.limits.max_streams_per_client == Some(0) {
+            return Err(anyhow!(
+                "clients must be allowed to play at least one stream"
+            ));
+        }
+        if self.keepalive.interval > 0 && self.keepalive.timeout <= self.keepalive.interval {
+            return Err(anyhow!(
+                "keepalive timeout must be longer than the ping interval"
+            ));
+        }
+        if self.buffers.socket_receive_buffer == Some(0) {
+            return Err(anyhow!("socket receive buffer must not be empty"));
+        }
+        if self.buffers.transmit_buffer_seconds.is_nan()
+            || self.buffers.transmit_buffer_seconds <= 0.0
+        {
+            return Err(anyhow!(
+                "transmit buffer must hold audio, got {} seconds",
+                self.buffers.transmit_buffer_seconds
+            ));
+        }
+
+        let mut names = HashSet::new();
+        for session in &self.sessions {
+            let name = &session.name;
+            if !names.insert(name) {
+                return Err(anyhow!("static session '{name}' is defined twice"));
+            }
+            if session.record.is_none() && session.hls.is_none() {
+                return Err(anyhow!(
+                    "static session '{name}' is neither recorded nor packaged for HLS"
+                ));
+            }
+            let descriptor = session
+                .session
+                .clone()
+                .descriptor()
+                .with_context(|| format!("static session '{name}'"))?;
+            if let Some(record) = &session.record {
+                record
+                    .validate(&descriptor)
+                    .with_context(|| format!("static session '{name}'"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn layered_config() {
+        let mut file = std::env::temp_dir();
+        file.push(format!("aes67-to-ws-{}.toml", std::process::id()));
+        fs::write(
+            &file,
+            r#"
+port = 8080
+rtcpReceiverReports = true
+shutdownTimeout = 30
+
+[interfaces]
+receive = "192.168.1.10"
+
+[limits]
+maxClientsPerAddress = 4
+
+[keepalive]
+interval = 10
+timeout = 60
+
+[[sessions]]
+name = "stage"
+session.sdp = """
+v=0
+o=- 1 1 IN IP4 192.168.1.20
+s=Stage
+c=IN IP4 239.69.3.4/32
+t=0 0
+m=audio 5004 RTP/AVP 97
+a=rtpmap:97 L24/48000/2
+a=ptime:1
+"""
+record = { maxDuration = 3600 }
+"#,
+        )
+        .unwrap();
+
+        let config = Config::from_args(Args {
+            config: Some(file.clone()),
+            port: Some(9000),
+            max_clients: Some(50),
+            transmit_buffer_seconds: Some(0.5),
+            keepalive_timeout: Some(30),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.limits.max_clients, Some(50));
+        assert_eq!(config.limits.max_clients_per_address, Some(4));
+        assert_eq!(config.keepalive.interval, 10);
+        assert_eq!(config.keepalive.timeout, 30);
+        assert_eq!(config.buffers.transmit_buffer_seconds, 0.5);
+        assert!(config.rtcp_receiver_reports);
+        assert_eq!(config.shutdown_timeout, 30);
+        assert_eq!(config.interfaces.receive, Ipv4Addr::new(192, 168, 1, 10));
+        assert_eq!(config.interfaces.ptp, Ipv4Addr::UNSPECIFIED);
+        assert_eq!(
+            config.sessions[0].record.as_ref().unwrap().max_duration,
+            Some(3600.0)
+        );
+
+        fs::write(
+            &file,
+            "[[sessions]]\nname = \"stage\"\nsession.sdp = \"\"\n",
+        )
+        .unwrap();
+        let error = Config::from_args(Args {
+            config: Some(file.clone()),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(
+            format!("{error:#}").contains("neither recorded"),
+            "{error:#}"
+        );
+
+        for toml in ["prot = 1", "shutdown_timeout = 1"] {
+            fs::write(&file, toml).unwrap();
+            assert!(Config::from_args(Args {
+                config: Some(file.clone()),
+                ..Default::default()
+            })
+            .is_err());
+        }
+        fs::remove_file(file).ok();
+    }
+
+    #[test]
+    fn tls_flags() {
+        let config = Config::from_args(Args {
+            tls_certificate: Some("cert.pem".into()),
+            tls_key: Some("key.pem".into()),
+            tls_port: Some(9443),
+            ..Default::default()
+        })
+        .unwrap();
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.port, Some(9443));
+        assert_eq!(tls.reload_interval, tls::default_reload_interval());
+
+        assert!(Config::from_args(Args {
+            tls_certificate: Some("cert.pem".into()),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(Config::from_args(Args {
+            tls_certificate: Some("cert.pem".into()),
+            tls_key: Some("key.pem".into()),
+            tls_port: Some(9999),
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
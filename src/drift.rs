@@ -0,0 +1,144 @@
+use std::{collections::VecDeque, time::Duration};
+
+/// Length of the windows over which the minimum transit time is taken.
+const WINDOW: Duration = Duration::from_secs(1);
+/// Number of windows the drift is estimated over.
+const WINDOWS: usize = 60;
+/// Number of windows needed before an estimate is reported.
+const MIN_WINDOWS: usize = 5;
+/// Timestamp jumps larger than this restart the estimation.
+const MAX_DISCONTINUITY: f64 = 1.0;
+
+/// Estimates the rate of a sender's media clock relative to the local clock from the RTP
+/// timestamps and arrival times of received packets.
+///
+/// Network jitter only ever delays packets, so the minimum transit time per window is a good
+/// proxy for the actual clock offset; the drift is the slope of a least squares fit through
+/// these minima.
+#[derive(Debug, Clone)]
+pub struct DriftEstimator {
+    sample_rate: f64,
+    origin: Option<(Duration, u32)>,
+    media_time: f64,
+    previous_timestamp: u32,
+    window: Option<(f64, f64)>,
+    minima: VecDeque<(f64, f64)>,
+}
+
+impl DriftEstimator {
+    pub fn new(sample_rate: u32) -> Self {
+        DriftEstimator {
+            sample_rate: sample_rate.max(1) as f64,
+            origin: None,
+            media_time: 0.0,
+            previous_timestamp: 0,
+            window: None,
+            minima: VecDeque::with_capacity(WINDOWS),
+        }
+    }
+
+    /// Feeds a packet's arrival time (relative to any fixed instant) and RTP timestamp into the
+    /// estimator and returns the current estimate in ppm, positive if the sender's clock runs
+    /// fast.
+    pub fn update(&mut self, arrival: Duration, timestamp: u32) -> Option<f64> {
+        let Some((origin, _)) = self.origin else {
+            self.reset(arrival, timestamp);
+            return None;
+        };
+
+        let elapsed = timestamp.wrapping_sub(self.previous_timestamp) as i32 as f64;
+        self.media_time += elapsed / self.sample_rate;
+        self.previous_timestamp = timestamp;
+
+        let local_time = arrival.saturating_sub(origin).as_secs_f64();
+        let transit = local_time - self.media_time;
+        if (transit - self.minima.back().map_or(0.0, |(_, t)| *t)).abs() > MAX_DISCONTINUITY {
+            log::debug!("RTP timestamp discontinuity, restarting drift estimation");
+            self.reset(arrival, timestamp);
+            return None;
+        }
+
+        match &mut self.window {
+            Some((start, minimum)) if local_time - *start < WINDOW.as_secs_f64() => {
+                *minimum = minimum.min(transit);
+            }
+            window => {
+                if let Some((start, minimum)) = window.take() {
+                    if self.minima.len() == WINDOWS {
+                        self.minima.pop_front();
+                    }
+                    self.minima.push_back((start, minimum));
+                }
+                *window = Some((local_time, transit));
+            }
+        }
+
+        self.estimate()
+    }
+
+    pub fn estimate(&self) -> Option<f64> {
+        if self.minima.len() < MIN_WINDOWS {
+            return None;
+        }
+        let n = self.minima.len() as f64;
+        let mean_time = self.minima.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_transit = self.minima.iter().map(|(_, d)| d).sum::<f64>() / n;
+        let (covariance, variance) =
+            self.minima
+                .iter()
+                .fold((0.0, 0.0), |(covariance, variance), (t, d)| {
+                    (
+                        covariance + (t - mean_time) * (d - mean_transit),
+                        variance + (t - mean_time).powi(2),
+                    )
+                });
+        if variance == 0.0 {
+            return None;
+        }
+        // a fast sender's media time outruns the local clock, so the transit time decreases
+        Some(-covariance / variance * 1_000_000.0)
+    }
+
+    fn reset(&mut self, arrival: Duration, timestamp: u32) {
+        self.origin = Some((arrival, timestamp));
+        self.media_time = 0.0;
+        self.previous_timestamp = timestamp;
+        self.window = None;
+        self.minima.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn estimate_drift_with_jitter() {
+        let mut estimator = DriftEstimator::new(48_000);
+        let mut rng = 12345u64;
+        let mut estimate = None;
+        // a sender running 100 ppm fast, sending 1 ms packets, starting close to a wrap-around
+        for packet in 0..30_000u64 {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            let jitter = Duration::from_micros(rng % 2_000);
+            let arrival = Duration::from_secs_f64(packet as f64 * 0.001 / 1.0001) + jitter;
+            let timestamp = (u32::MAX - 100_000).wrapping_add(packet as u32 * 48);
+            estimate = estimator.update(arrival, timestamp);
+        }
+        let estimate = estimate.unwrap();
+        assert!((estimate - 100.0).abs() < 5.0, "estimated {estimate} ppm");
+    }
+
+    #[test]
+    fn restart_on_discontinuity() {
+        let mut estimator = DriftEstimator::new(48_000);
+        for packet in 0..10_000u32 {
+            estimator.update(Duration::from_millis(packet as u64), packet * 48);
+        }
+        assert!(estimator.estimate().is_some_and(|ppm| ppm.abs() < 1.0));
+        estimator.update(Duration::from_millis(10_000), 48_000 * 5_000);
+        assert_eq!(estimator.estimate(), None);
+    }
+}
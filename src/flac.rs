@@ -0,0 +1,351 @@
+use anyhow::anyhow;
+
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_RICE_PARAMETER: u32 = 30;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamInfo {
+    pub block_size: u16,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+}
+
+impl StreamInfo {
+    pub fn new(
+        block_size: u16,
+        sample_rate: u32,
+        channels: u16,
+        bits_per_sample: u16,
+    ) -> anyhow::Result<Self> {
+        if channels == 0 || channels > 8 {
+            return Err(anyhow!("FLAC supports 1 to 8 channels, got {channels}"));
+        }
+        if !(4..=32).contains(&bits_per_sample) {
+            return Err(anyhow!("unsupported FLAC sample size: {bits_per_sample}"));
+        }
+        if sample_rate == 0 || sample_rate >= 1 << 20 {
+            return Err(anyhow!("unsupported FLAC sample rate: {sample_rate}"));
+        }
+        if block_size < 16 {
+            return Err(anyhow!(
+                "FLAC block size must be at least 16, got {block_size}"
+            ));
+        }
+        Ok(StreamInfo {
+            block_size,
+            sample_rate,
+            channels,
+            bits_per_sample,
+        })
+    }
+
+    /// The 34 byte STREAMINFO metadata block body. Frame sizes, total samples and MD5 are
+    /// left unknown since the stream is encoded live.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.write(self.block_size as u64, 16);
+        w.write(self.block_size as u64, 16);
+        w.write(0, 24);
+        w.write(0, 24);
+        w.write(self.sample_rate as u64, 20);
+        w.write(self.channels as u64 - 1, 3);
+        w.write(self.bits_per_sample as u64 - 1, 5);
+        w.write(0, 36);
+        w.write(0, 64);
+        w.write(0, 64);
+        w.into_bytes()
+    }
+
+    /// Encodes one frame from channel interleaved samples. The number of samples per channel
+    /// may be smaller than the block size for the last frame of a stream.
+    pub fn encode_frame(&self, frame_number: u64, samples: &[i32]) -> Vec<u8> {
+        let channels = self.channels as usize;
+        let block_size = samples.len() / channels;
+
+        let mut w = BitWriter::default();
+        w.write(0b11_1111_1111_1110, 14);
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write(0b0111, 4);
+        w.write(self.sample_rate_code() as u64, 4);
+        w.write(channels as u64 - 1, 4);
+        w.write(self.sample_size_code() as u64, 3);
+        w.write(0, 1);
+        write_utf8_number(&mut w, frame_number);
+        w.write(block_size as u64 - 1, 16);
+        let crc = crc8(w.bytes());
+        w.write(crc as u64, 8);
+
+        let mut channel = Vec::with_capacity(block_size);
+        for c in 0..channels {
+            channel.clear();
+            channel.extend(samples.iter().skip(c).step_by(channels));
+            self.write_subframe(&mut w, &channel);
+        }
+
+        w.align();
+        let crc = crc16(w.bytes());
+        w.write(crc as u64, 16);
+        w.into_bytes()
+    }
+
+    fn write_subframe(&self, w: &mut BitWriter, samples: &[i32]) {
+        let bps = self.bits_per_sample as u32;
+
+        if samples.iter().all(|s| *s == samples[0]) {
+            w.write(0, 1);
+            w.write(0b000000, 6);
+            w.write(0, 1);
+            w.write_signed(samples[0] as i64, bps);
+            return;
+        }
+
+        let verbatim_bits = samples.len() as u64 * bps as u64;
+        let best = (0..=MAX_FIXED_ORDER.min(samples.len() - 1))
+            .map(|order| {
+                let residual = fixed_residual(samples, order);
+                let (parameter, bits) = rice_parameter(&residual);
+                (order, residual, parameter, bits + order as u64 * bps as u64)
+            })
+            .min_by_key(|(_, _, _, bits)| *bits);
+
+        match best {
+            Some((order, residual, parameter, bits)) if bits < verbatim_bits => {
+                w.write(0, 1);
+                w.write(0b001000 | order as u64, 6);
+                w.write(0, 1);
+                for sample in &samples[..order] {
+                    w.write_signed(*sample as i64, bps);
+                }
+                let (method, parameter_bits) = if parameter < 15 { (0, 4) } else { (1, 5) };
+                w.write(method, 2);
+                w.write(0, 4);
+                w.write(parameter as u64, parameter_bits);
+                for r in residual {
+                    let u = zigzag(r);
+                    let quotient = u >> parameter;
+                    w.write_unary(quotient);
+                    w.write(u & ((1 << parameter) - 1), parameter);
+                }
+            }
+            _ => {
+                w.write(0, 1);
+                w.write(0b000001, 6);
+                w.write(0, 1);
+                for sample in samples {
+                    w.write_signed(*sample as i64, bps);
+                }
+            }
+        }
+    }
+
+    fn sample_rate_code(&self) -> u8 {
+        match self.sample_rate {
+            88_200 => 0b0001,
+            176_400 => 0b0010,
+            192_000 => 0b0011,
+            8_000 => 0b0100,
+            16_000 => 0b0101,
+            22_050 => 0b0110,
+            24_000 => 0b0111,
+            32_000 => 0b1000,
+            44_100 => 0b1001,
+            48_000 => 0b1010,
+            96_000 => 0b1011,
+            _ => 0b0000,
+        }
+    }
+
+    fn sample_size_code(&self) -> u8 {
+        match self.bits_per_sample {
+            8 => 0b001,
+            12 => 0b010,
+            16 => 0b100,
+            20 => 0b101,
+            24 => 0b110,
+            32 => 0b111,
+            _ => 0b000,
+        }
+    }
+}
+
+fn fixed_residual(samples: &[i32], order: usize) -> Vec<i64> {
+    samples
+        .windows(order + 1)
+        .map(|w| {
+            let s = |i: usize| w[order - i] as i64;
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Picks the rice parameter that minimizes the encoded size of the residual and returns it
+/// together with the resulting number of bits, including the residual header.
+fn rice_parameter(residual: &[i64]) -> (u32, u64) {
+    let len = residual.len().max(1) as u64;
+    let sum: u64 = residual.iter().map(|r| zigzag(*r)).sum();
+    let mean = sum / len;
+    let estimate = (64 - mean.leading_zeros()).min(MAX_RICE_PARAMETER);
+
+    (estimate.saturating_sub(1)..=(estimate + 1).min(MAX_RICE_PARAMETER))
+        .map(|k| {
+            let bits = residual
+                .iter()
+                .map(|r| (zigzag(*r) >> k) + 1 + k as u64)
+                .sum::<u64>();
+            let header = if k < 15 { 2 + 4 + 4 } else { 2 + 4 + 5 };
+            (k, bits + header)
+        })
+        .min_by_key(|(_, bits)| *bits)
+        .expect("range is never empty")
+}
+
+fn write_utf8_number(w: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        w.write(value, 8);
+        return;
+    }
+    let bits = 64 - value.leading_zeros();
+    // each continuation byte carries 6 bits, the leading byte 6 - n bits for n continuation bytes
+    let continuation = (1..=6).find(|n| bits <= 6 - n + 6 * n).unwrap_or(6);
+    let lead_mask = !(0xFFu64 >> (continuation + 1)) & 0xFF;
+    w.write(lead_mask | (value >> (6 * continuation)), 8);
+    for i in (0..continuation).rev() {
+        w.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        for i in (0..bits).rev() {
+            self.current = (self.current << 1) | ((value >> i) & 1);
+            self.bits += 1;
+            if self.bits == 8 {
+                self.bytes.push(self.current as u8);
+                self.current = 0;
+                self.bits = 0;
+            }
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64 & ((1u64 << bits) - 1), bits);
+    }
+
+    fn write_unary(&mut self, zeros: u64) {
+        for _ in 0..zeros {
+            self.write(0, 1);
+        }
+        self.write(1, 1);
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+
+    /// The completed bytes written so far. Callers must make sure the writer is byte aligned.
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn streaminfo_layout() {
+        let info = StreamInfo::new(4096, 48_000, 2, 24).unwrap();
+        let bytes = info.to_bytes();
+        assert_eq!(bytes.len(), 34);
+        assert_eq!(&bytes[0..4], &[0x10, 0x00, 0x10, 0x00]);
+        // 48000 Hz (20 bits), 2 channels - 1 (3 bits), 24 bps - 1 (5 bits)
+        assert_eq!(&bytes[10..13], &[0x0B, 0xB8, 0x03]);
+        assert_eq!(bytes[13] >> 4, 0x07);
+    }
+
+    #[test]
+    fn frame_header_and_crc() {
+        let info = StreamInfo::new(16, 48_000, 1, 16).unwrap();
+        let samples: Vec<i32> = (0..16).map(|i| i * 100).collect();
+        let frame = info.encode_frame(0, &samples);
+        assert_eq!(&frame[0..2], &[0xFF, 0xF8]);
+        assert_eq!(crc8(&frame[0..7]), frame[7]);
+        assert_eq!(crc16(&frame), 0);
+    }
+
+    #[test]
+    fn utf8_frame_numbers() {
+        let encode = |n| {
+            let mut w = BitWriter::default();
+            write_utf8_number(&mut w, n);
+            w.into_bytes()
+        };
+        assert_eq!(encode(0x7F), vec![0x7F]);
+        assert_eq!(encode(0x80), vec![0xC2, 0x80]);
+        assert_eq!(encode(0x800), vec![0xE0, 0xA0, 0x80]);
+    }
+
+    #[test]
+    fn fixed_prediction_compresses_ramps() {
+        let info = StreamInfo::new(1024, 48_000, 1, 24).unwrap();
+        let samples: Vec<i32> = (0..1024).map(|i| i * 1000).collect();
+        let frame = info.encode_frame(0, &samples);
+        assert!(frame.len() < 1024 * 3 / 10);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{pcm, SessionDescriptor};
+use anyhow::anyhow;
+use rtp_rs::{RtpPacketBuilder, Seq};
+use serde::{Deserialize, Serialize};
+use std::{f64::consts::TAU, time::Duration};
+use tokio::time::{interval, Interval, MissedTickBehavior};
+
+const PAYLOAD_TYPE: u8 = 98;
+const IDENT_CYCLE_SECONDS: f64 = 4.0;
+const IDENT_CLICK_SPACING_SECONDS: f64 = 0.15;
+const IDENT_CLICK_SECONDS: f64 = 0.002;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Signal {
+    /// A sine on every channel, channel n playing n times the base frequency.
+    Sine {
+        #[serde(default = "default_frequency")]
+        frequency: f32,
+    },
+    PinkNoise,
+    /// Channel n plays n short clicks at the start of every identification cycle.
+    Ident,
+    Silence,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeneratorSession {
+    pub signal: Signal,
+    /// Signal level in dBFS.
+    #[serde(default = "default_level")]
+    pub level: f32,
+    pub descriptor: SessionDescriptor,
+}
+
+fn default_frequency() -> f32 {
+    440.0
+}
+
+fn default_level() -> f32 {
+    -18.0
+}
+
+/// Produces RTP packets of a synthetic test signal in real time.
+pub struct Generator {
+    signal: Signal,
+    amplitude: f64,
+    descriptor: SessionDescriptor,
+    frames_per_packet: u32,
+    frame: u64,
+    timestamp: u32,
+    sequence_number: u16,
+    ssrc: u32,
+    pink: Vec<[f64; 7]>,
+    rng: u64,
+    ticks: Interval,
+}
+
+impl Generator {
+    pub fn new(session: GeneratorSession) -> anyhow::Result<Self> {
+        let descriptor = session.descriptor;
+        let frames_per_packet = descriptor.buffer_size_frames();
+        if frames_per_packet == 0 || descriptor.channels == 0 {
+            return Err(anyhow!("invalid generator session: {descriptor:?}"));
+        }
+
+        let packet_duration =
+            Duration::from_secs_f64(frames_per_packet as f64 / descriptor.sample_rate as f64);
+        let mut ticks = interval(packet_duration);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Burst);
+
+        let since_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let seed = since_epoch.as_nanos() as u64 | 1;
+        // the media clock is the system clock, like for transmitted streams
+        let timestamp = ((since_epoch.as_secs_f64() * descriptor.sample_rate as f64) as u64 as u32)
+            .wrapping_add(descriptor.media_clock_offset.unwrap_or(0));
+
+        Ok(Generator {
+            signal: session.signal,
+            amplitude: 10f64.powf(session.level as f64 / 20.0),
+            pink: vec![[0.0; 7]; descriptor.channels as usize],
+            descriptor,
+            frames_per_packet,
+            frame: 0,
+            timestamp,
+            sequence_number: seed as u16,
+            ssrc: (seed >> 16) as u32,
+            rng: seed,
+            ticks,
+        })
+    }
+
+    /// Waits until the next packet is due and returns it.
+    pub async fn next_packet(&mut self) -> anyhow::Result<Vec<u8>> {
+        self.ticks.tick().await;
+
+        let timestamp = self.timestamp.wrapping_add(self.frame as u32);
+        let samples = self.render();
+        let payload = pcm::encode(&samples, &self.descriptor.bit_depth);
+        let packet = RtpPacketBuilder::new()
+            .payload_type(PAYLOAD_TYPE)
+            .ssrc(self.ssrc)
+            .sequence(Seq::from(self.sequence_number))
+            .timestamp(timestamp)
+            .payload(&payload)
+            .build()
+            .map_err(|e| anyhow!("{e:?}"))?;
+
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        Ok(packet)
+    }
+
+    fn render(&mut self) -> Vec<f32> {
+        let channels = self.descriptor.channels as usize;
+        let sample_rate = self.descriptor.sample_rate as f64;
+        let mut samples = Vec::with_capacity(self.frames_per_packet as usize * channels);
+
+        for _ in 0..self.frames_per_packet {
+            let t = self.frame as f64 / sample_rate;
+            for channel in 0..channels {
+                let value = match &self.signal {
+                    Signal::Sine { frequency } => {
+                        (TAU * *frequency as f64 * (channel + 1) as f64 * t).sin()
+                    }
+                    Signal::PinkNoise => {
+                        let white = self.white_noise();
+                        pink_noise(&mut self.pink[channel], white)
+                    }
+                    Signal::Ident => ident_click(channel, t),
+                    Signal::Silence => 0.0,
+                };
+                samples.push((value * self.amplitude) as f32);
+            }
+            self.frame += 1;
+        }
+
+        samples
+    }
+
+    fn white_noise(&mut self) -> f64 {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+    }
+}
+
+/// Paul Kellet's refined pink noise filter, normalized to roughly unity peak level.
+fn pink_noise(b: &mut [f64; 7], white: f64) -> f64 {
+    b[0] = 0.99886 * b[0] + white * 0.0555179;
+    b[1] = 0.99332 * b[1] + white * 0.0750759;
+    b[2] = 0.96900 * b[2] + white * 0.1538520;
+    b[3] = 0.86650 * b[3] + white * 0.3104856;
+    b[4] = 0.55000 * b[4] + white * 0.5329522;
+    b[5] = -0.7616 * b[5] - white * 0.0168980;
+    let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+    b[6] = white * 0.115926;
+    (pink * 0.2).clamp(-1.0, 1.0)
+}
+
+fn ident_click(channel: usize, t: f64) -> f64 {
+    let t = t % IDENT_CYCLE_SECONDS;
+    let click = (t / IDENT_CLICK_SPACING_SECONDS) as usize;
+    let offset = t - click as f64 * IDENT_CLICK_SPACING_SECONDS;
+    if click <= channel && offset < IDENT_CLICK_SECONDS {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ident_clicks_per_channel() {
+        let clicks = |channel| {
+            (0..(IDENT_CYCLE_SECONDS * 48_000.0) as usize)
+                .map(|i| ident_click(channel, i as f64 / 48_000.0))
+                .collect::<Vec<_>>()
+                .windows(2)
+                .filter(|w| w[0] == 0.0 && w[1] == 1.0)
+                .count()
+                + 1
+        };
+        assert_eq!(clicks(0), 1);
+        assert_eq!(clicks(3), 4);
+    }
+}
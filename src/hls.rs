@@ -0,0 +1,292 @@
+use crate::{
+    flac::StreamInfo,
+    mp4::{self, Sample},
+    pcm,
+    stream::{Packet, Stream, StreamHandle},
+    BitDepth, SessionDescriptor,
+};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+use tokio::{spawn, sync::mpsc};
+
+const FLAC_BLOCK_SIZE: u16 = 4096;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HlsConfig {
+    /// Target duration of a single media segment in seconds.
+    #[serde(default = "default_segment_duration")]
+    pub segment_duration: f32,
+    /// Number of segments kept in the rolling live playlist.
+    #[serde(default = "default_window")]
+    pub window: usize,
+}
+
+impl Default for HlsConfig {
+    fn default() -> Self {
+        Self {
+            segment_duration: default_segment_duration(),
+            window: default_window(),
+        }
+    }
+}
+
+fn default_segment_duration() -> f32 {
+    6.0
+}
+
+fn default_window() -> usize {
+    6
+}
+
+#[derive(Debug)]
+struct Segment {
+    sequence_number: u64,
+    duration: f64,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Output {
+    init_segment: Vec<u8>,
+    segments: VecDeque<Segment>,
+}
+
+struct Packager {
+    descriptor: SessionDescriptor,
+    stream: StreamHandle,
+    output: Arc<Mutex<Output>>,
+}
+
+/// Keeps track of all running HLS packagers by name.
+#[derive(Clone, Default)]
+pub struct HlsRegistry {
+    packagers: Arc<Mutex<HashMap<String, Packager>>>,
+    max_packagers: Option<usize>,
+}
+
+impl HlsRegistry {
+    /// Limits the number of concurrently running packagers.
+    pub fn with_limit(mut self, max_packagers: Option<usize>) -> Self {
+        self.max_packagers = max_packagers;
+        self
+    }
+
+    pub async fn start(
+        &self,
+        name: String,
+        mut stream: Stream,
+        config: HlsConfig,
+    ) -> anyhow::Result<()> {
+        let sd = stream.descriptor.clone();
+        if config.segment_duration <= 0.0 || config.window == 0 {
+            return Err(anyhow!("invalid HLS config: {config:?}"));
+        }
+
+        let bits_per_sample = match sd.bit_depth {
+            BitDepth::L8 => 8,
+            BitDepth::L16 => 16,
+            BitDepth::L20 => 20,
+            _ => 24,
+        };
+        let info = StreamInfo::new(
+            FLAC_BLOCK_SIZE,
+            sd.sample_rate,
+            sd.channels,
+            bits_per_sample,
+        )?;
+
+        if let Some(max) = self.max_packagers {
+            let packagers = self.packagers.lock().expect("mutex poisoned");
+            if !packagers.contains_key(&name) && packagers.len() >= max {
+                return Err(anyhow!("the maximum of {max} HLS packagers is running"));
+            }
+        }
+        self.stop(&name);
+
+        log::info!("Starting HLS packager '{name}' for {sd:?}");
+        let (payload_tx, payload_rx) = mpsc::unbounded_channel();
+        let handle = stream.play(payload_tx).await?;
+
+        let output = Arc::new(Mutex::new(Output {
+            init_segment: mp4::init_segment(&info),
+            segments: VecDeque::new(),
+        }));
+
+        spawn(package(
+            payload_rx,
+            sd.clone(),
+            info,
+            config,
+            output.clone(),
+        ));
+
+        self.packagers.lock().expect("mutex poisoned").insert(
+            name,
+            Packager {
+                descriptor: sd,
+                stream: handle,
+                output,
+            },
+        );
+
+        Ok(())
+    }
+
+    pub fn stop(&self, name: &str) -> bool {
+        if let Some(packager) = self.packagers.lock().expect("mutex poisoned").remove(name) {
+            log::info!("Stopping HLS packager '{name}'");
+            packager.stream.stop();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Stops all packagers and waits until they have left their sessions.
+    pub async fn stop_all(&self) {
+        let packagers: Vec<_> = self
+            .packagers
+            .lock()
+            .expect("mutex poisoned")
+            .drain()
+            .collect();
+        for (name, mut packager) in packagers {
+            log::info!("Stopping HLS packager '{name}'");
+            packager.stream.join().await;
+        }
+    }
+
+    /// The live playlist, with `query` appended to the URIs of its segments to pass on the
+    /// credentials it was requested with.
+    pub fn playlist(&self, name: &str, query: &str) -> Option<String> {
+        let query = if query.is_empty() {
+            String::new()
+        } else {
+            format!("?{query}")
+        };
+        self.with_output(name, |output| {
+            let target_duration = output
+                .segments
+                .iter()
+                .map(|s| s.duration.ceil() as u64)
+                .max()
+                .unwrap_or(1);
+            let media_sequence = output
+                .segments
+                .front()
+                .map(|s| s.sequence_number)
+                .unwrap_or(0);
+
+            let mut playlist = String::new();
+            writeln!(playlist, "#EXTM3U").ok();
+            writeln!(playlist, "#EXT-X-VERSION:7").ok();
+            writeln!(playlist, "#EXT-X-TARGETDURATION:{target_duration}").ok();
+            writeln!(playlist, "#EXT-X-MEDIA-SEQUENCE:{media_sequence}").ok();
+            writeln!(playlist, "#EXT-X-MAP:URI=\"init.mp4{query}\"").ok();
+            for segment in &output.segments {
+                writeln!(playlist, "#EXTINF:{:.3},", segment.duration).ok();
+                writeln!(playlist, "segment{}.m4s{query}", segment.sequence_number).ok();
+            }
+            playlist
+        })
+    }
+
+    /// The session packaged under the given name.
+    pub fn descriptor(&self, name: &str) -> Option<SessionDescriptor> {
+        let packagers = self.packagers.lock().expect("mutex poisoned");
+        packagers.get(name).map(|p| p.descriptor.clone())
+    }
+
+    pub fn init_segment(&self, name: &str) -> Option<Vec<u8>> {
+        self.with_output(name, |output| output.init_segment.clone())
+    }
+
+    pub fn segment(&self, name: &str, sequence_number: u64) -> Option<Vec<u8>> {
+        self.with_output(name, |output| {
+            output
+                .segments
+                .iter()
+                .find(|s| s.sequence_number == sequence_number)
+                .map(|s| s.data.clone())
+        })
+        .flatten()
+    }
+
+    fn with_output<T>(&self, name: &str, f: impl FnOnce(&Output) -> T) -> Option<T> {
+        let packagers = self.packagers.lock().expect("mutex poisoned");
+        let packager = packagers.get(name)?;
+        let output = packager.output.lock().expect("mutex poisoned");
+        Some(f(&output))
+    }
+}
+
+async fn package(
+    mut payload_rx: mpsc::UnboundedReceiver<Packet>,
+    sd: SessionDescriptor,
+    info: StreamInfo,
+    config: HlsConfig,
+    output: Arc<Mutex<Output>>,
+) {
+    let block_len = info.block_size as usize * info.channels as usize;
+    let segment_frames = (config.segment_duration as f64 * info.sample_rate as f64) as u64;
+
+    let mut pcm = Vec::with_capacity(block_len * 2);
+    let mut samples = Vec::new();
+    let mut segment_frame_count = 0;
+    let mut frame_number = 0;
+    let mut decode_time = 0;
+    let mut sequence_number = 0;
+
+    while let Some(Packet { payload, .. }) = payload_rx.recv().await {
+        pcm.extend(
+            pcm::decode(&payload, &sd.bit_depth)
+                .into_iter()
+                .map(|s| pcm::quantize(s, info.bits_per_sample)),
+        );
+
+        while pcm.len() >= block_len {
+            let block: Vec<i32> = pcm.drain(..block_len).collect();
+            samples.push(Sample {
+                duration: info.block_size as u32,
+                data: info.encode_frame(frame_number, &block),
+            });
+            frame_number += 1;
+            segment_frame_count += info.block_size as u64;
+
+            if segment_frame_count >= segment_frames {
+                let data = mp4::media_segment(sequence_number as u32, decode_time, &samples);
+                let segment = Segment {
+                    sequence_number,
+                    duration: segment_frame_count as f64 / info.sample_rate as f64,
+                    data,
+                };
+                log::debug!(
+                    "Finished HLS segment {} ({:.3} s, {} bytes)",
+                    segment.sequence_number,
+                    segment.duration,
+                    segment.data.len()
+                );
+
+                let mut output = output.lock().expect("mutex poisoned");
+                output.segments.push_back(segment);
+                while output.segments.len() > config.window {
+                    output.segments.pop_front();
+                }
+                drop(output);
+
+                samples.clear();
+                decode_time += segment_frame_count;
+                segment_frame_count = 0;
+                sequence_number += 1;
+            }
+        }
+    }
+
+    log::info!("HLS packager closed.");
+}
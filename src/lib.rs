@@ -1,6 +1,28 @@
+pub mod am824;
+pub mod auth;
+pub mod clients;
+pub mod config;
+pub mod drift;
+pub mod flac;
+pub mod generator;
+pub mod hls;
+pub mod loudness;
+pub mod meter;
+pub mod mp4;
+pub mod pcap;
+pub mod pcm;
 pub mod poem;
+pub mod policy;
+pub mod ptp;
+pub mod recorder;
+pub mod resample;
+pub mod rtcp;
+pub mod sap;
 pub mod sdp;
 pub mod stream;
+pub mod tls;
+pub mod transmitter;
+pub mod watchdog;
 
 use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
@@ -13,7 +35,22 @@ pub struct SessionDescriptor {
     pub bit_depth: BitDepth,
     pub channels: u16,
     pub sample_rate: u32,
+    /// Packet time in milliseconds (`a=ptime`), rounded for packet times such as 0.333 ms.
     pub packet_time: f32,
+    /// Exact number of frames per packet (`a=framecount`), if known.
+    #[serde(default)]
+    pub frames_per_packet: Option<u32>,
+    /// Longest packet time in milliseconds the sender may use (`a=maxptime`).
+    #[serde(default)]
+    pub max_packet_time: Option<f32>,
+    #[serde(default)]
+    pub session_name: Option<String>,
+    /// Reference clock of the RTP timestamps (`a=ts-refclk`).
+    #[serde(default)]
+    pub reference_clock: Option<ReferenceClock>,
+    /// Offset of the RTP timestamps from the media clock (`a=mediaclk:direct=`).
+    #[serde(default)]
+    pub media_clock_offset: Option<u32>,
 }
 
 impl Default for SessionDescriptor {
@@ -25,39 +62,147 @@ impl Default for SessionDescriptor {
             channels: 2,
             sample_rate: 44100,
             packet_time: 1.0,
+            frames_per_packet: None,
+            max_packet_time: None,
+            session_name: None,
+            reference_clock: None,
+            media_clock_offset: None,
         }
     }
 }
 
 impl SessionDescriptor {
+    /// Fails unless the session carries audio that can be processed at all.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.channels == 0 {
+            return Err(anyhow!("session has no channels"));
+        }
+        if self.sample_rate == 0 {
+            return Err(anyhow!("session has no sample rate"));
+        }
+        if self.buffer_size_frames() == 0 {
+            return Err(anyhow!("session packets carry no frames"));
+        }
+        Ok(())
+    }
+
     pub fn buffer_size_bytes(&self) -> u32 {
-        let channels = self.channels as u32;
-        let bit_depth = self.bit_depth.bits() as u32;
-        self.buffer_size_frames() * bit_depth * channels
+        let samples = self.buffer_size_frames() as usize * self.channels as usize;
+        self.bit_depth.bytes(samples) as u32
     }
 
+    /// Frames per packet, preferring the exact frame count over the packet time.
     pub fn buffer_size_frames(&self) -> u32 {
-        let packet_time = self.packet_time;
-        let sample_rate = self.sample_rate;
-        (packet_time * sample_rate as f32 / 1_000.0) as u32
+        self.frames_per_packet
+            .unwrap_or_else(|| packet_time_frames(self.packet_time, self.sample_rate))
     }
+
+    /// Most frames a packet of this session may carry.
+    pub fn max_buffer_size_frames(&self) -> u32 {
+        let max = self.max_packet_time.map_or(0, |max_packet_time| {
+            packet_time_frames(max_packet_time, self.sample_rate)
+        });
+        max.max(self.buffer_size_frames())
+    }
+
+    /// Checks that a payload holds whole frames, as many as a packet of this session carries.
+    pub fn validate_payload_size(&self, len: usize) -> Result<(), PayloadSizeError> {
+        let expected = self.buffer_size_bytes() as usize;
+        if len == expected {
+            return Ok(());
+        }
+        let samples = self.channels.max(1) as usize;
+        let frames = len * 8 / (self.bit_depth.bits() as usize * samples);
+        if frames == 0 || self.bit_depth.bytes(frames * samples) != len {
+            Err(PayloadSizeError::PartialFrame { expected, len })
+        } else if self.max_packet_time.is_some() && frames <= self.max_buffer_size_frames() as usize
+        {
+            // the packet time may change up to maxptime
+            Ok(())
+        } else {
+            Err(PayloadSizeError::UnexpectedLength { expected, len })
+        }
+    }
+}
+
+/// Converts a packet time in milliseconds to frames, rounding packet times such as 0.333 ms.
+fn packet_time_frames(packet_time: f32, sample_rate: u32) -> u32 {
+    (packet_time as f64 * sample_rate as f64 / 1_000.0).round() as u32
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PayloadSizeError {
+    /// The payload holds whole frames, but not as many as announced.
+    UnexpectedLength { expected: usize, len: usize },
+    /// The payload does not hold a whole number of frames, so its samples cannot be assigned to
+    /// channels.
+    PartialFrame { expected: usize, len: usize },
+}
+
+impl fmt::Display for PayloadSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadSizeError::UnexpectedLength { expected, len } => {
+                write!(f, "payload of {len} bytes, expected {expected} bytes")
+            }
+            PayloadSizeError::PartialFrame { expected, len } => write!(
+                f,
+                "payload of {len} bytes does not hold whole frames, expected {expected} bytes"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReferenceClock {
+    #[serde(rename_all = "camelCase")]
+    Ptp {
+        /// PTP profile version, e.g. `IEEE1588-2008`.
+        version: String,
+        /// Clock identity of the grandmaster, `None` for a traceable clock.
+        grandmaster: Option<String>,
+        domain: Option<u8>,
+    },
+    /// Any other reference clock source, verbatim.
+    Other(String),
+}
+
+/// Sample encoding of a session, named after its RTP encoding name.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BitDepth {
+    /// 8 bit unsigned samples, offset by 128.
+    L8,
     L16,
+    /// 20 bit samples, two of which are packed into five bytes.
+    L20,
     L24,
     L32,
-    FloatingPoint,
+    /// 32 bit IEEE 754 floats in network byte order.
+    #[serde(alias = "FloatingPoint")]
+    F32,
+    F32LE,
+    /// 64 bit IEEE 754 floats in network byte order.
+    F64,
+    F64LE,
+    /// 24 bit AES3 subframes, SMPTE ST 2110-31.
+    AM824,
 }
 
 impl fmt::Display for BitDepth {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            BitDepth::L8 => write!(f, "L8"),
             BitDepth::L16 => write!(f, "L16"),
+            BitDepth::L20 => write!(f, "L20"),
             BitDepth::L24 => write!(f, "L24"),
             BitDepth::L32 => write!(f, "L32"),
-            BitDepth::FloatingPoint => write!(f, "Floating Point"),
+            BitDepth::F32 => write!(f, "F32"),
+            BitDepth::F32LE => write!(f, "F32LE"),
+            BitDepth::F64 => write!(f, "F64"),
+            BitDepth::F64LE => write!(f, "F64LE"),
+            BitDepth::AM824 => write!(f, "AM824"),
         }
     }
 }
@@ -65,35 +210,57 @@ impl fmt::Display for BitDepth {
 impl BitDepth {
     pub fn bits(&self) -> u16 {
         match self {
+            BitDepth::L8 => 8,
             BitDepth::L16 => 16,
+            BitDepth::L20 => 20,
             BitDepth::L24 => 24,
             BitDepth::L32 => 32,
-            BitDepth::FloatingPoint => 32,
+            BitDepth::F32 | BitDepth::F32LE => 32,
+            BitDepth::F64 | BitDepth::F64LE => 64,
+            BitDepth::AM824 => 32,
         }
     }
 
-    pub fn floating_point(&self) -> bool {
+    /// Size of the given number of samples in bytes.
+    pub fn bytes(&self, samples: usize) -> usize {
+        (samples * self.bits() as usize).div_ceil(8)
+    }
+
+    /// The format the audio is delivered in to clients and recordings: big endian samples of
+    /// whole bytes, with AM824 subframes stripped down to their audio.
+    pub fn pcm(&self) -> BitDepth {
         match self {
-            BitDepth::FloatingPoint => true,
-            _ => false,
+            BitDepth::L20 | BitDepth::AM824 => BitDepth::L24,
+            BitDepth::F32LE => BitDepth::F32,
+            BitDepth::F64LE => BitDepth::F64,
+            other => other.clone(),
         }
     }
+
+    pub fn floating_point(&self) -> bool {
+        matches!(
+            self,
+            BitDepth::F32 | BitDepth::F32LE | BitDepth::F64 | BitDepth::F64LE
+        )
+    }
 }
 
 impl FromStr for BitDepth {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.contains("16") {
-            Ok(BitDepth::L16)
-        } else if s.contains("24") {
-            Ok(BitDepth::L24)
-        } else if s.contains("32") {
-            Ok(BitDepth::L32)
-        } else if s.to_lowercase().contains("float") {
-            Ok(BitDepth::FloatingPoint)
-        } else {
-            Err(anyhow!("invalid bit depth: {s}"))
+        match s.trim().to_uppercase().as_str() {
+            "L8" => Ok(BitDepth::L8),
+            "L16" => Ok(BitDepth::L16),
+            "L20" => Ok(BitDepth::L20),
+            "L24" => Ok(BitDepth::L24),
+            "L32" => Ok(BitDepth::L32),
+            "F32" | "F32BE" | "FLOAT32" | "FLOATING POINT" => Ok(BitDepth::F32),
+            "F32LE" | "FLOAT32LE" => Ok(BitDepth::F32LE),
+            "F64" | "F64BE" | "FLOAT64" => Ok(BitDepth::F64),
+            "F64LE" | "FLOAT64LE" => Ok(BitDepth::F64LE),
+            "AM824" => Ok(BitDepth::AM824),
+            _ => Err(anyhow!("invalid bit depth: {s}")),
         }
     }
 }
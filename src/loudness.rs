@@ -0,0 +1,300 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, f64::consts::PI};
+
+/// Loudness is measured in 100 ms steps, the overlap of consecutive momentary blocks.
+const STEP_SECONDS: f64 = 0.1;
+const MOMENTARY_STEPS: usize = 4;
+const SHORT_TERM_STEPS: usize = 30;
+const ABSOLUTE_GATE: f64 = -70.0;
+const INTEGRATED_RELATIVE_GATE: f64 = -10.0;
+const RANGE_RELATIVE_GATE: f64 = -20.0;
+
+/// Role of a channel in the measured layout, determining its weight.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LoudnessChannel {
+    Left,
+    Right,
+    Center,
+    Lfe,
+    LeftSurround,
+    RightSurround,
+    /// Not part of the measured programme.
+    Unused,
+}
+
+impl LoudnessChannel {
+    fn weight(&self) -> f64 {
+        match self {
+            LoudnessChannel::Left | LoudnessChannel::Right | LoudnessChannel::Center => 1.0,
+            LoudnessChannel::LeftSurround | LoudnessChannel::RightSurround => 1.41,
+            LoudnessChannel::Lfe | LoudnessChannel::Unused => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessConfig {
+    /// Role of each channel of the stream. Channels not listed are ignored; without a layout all
+    /// channels are weighted equally.
+    #[serde(default)]
+    pub layout: Option<Vec<LoudnessChannel>>,
+}
+
+/// Loudness values in LUFS and loudness range in LU, `None` until enough audio was measured.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessFrame {
+    pub momentary: Option<f32>,
+    pub short_term: Option<f32>,
+    pub integrated: Option<f32>,
+    pub loudness_range: Option<f32>,
+}
+
+/// Measures loudness according to ITU-R BS.1770 / EBU R128 from interleaved, normalized samples.
+pub struct LoudnessMeter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    step_frames: usize,
+    frames_in_step: usize,
+    step_energy: Vec<f64>,
+    steps: VecDeque<f64>,
+    /// Mean square of every momentary block since the last reset.
+    momentary_blocks: Vec<f64>,
+    /// Mean square of every short-term block since the last reset.
+    short_term_blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: u16, sample_rate: u32, config: &LoudnessConfig) -> anyhow::Result<Self> {
+        if channels == 0 || sample_rate < 8_000 {
+            return Err(anyhow!("unsupported loudness measurement format"));
+        }
+        let channels = channels as usize;
+        let weights = match &config.layout {
+            Some(layout) => (0..channels)
+                .map(|c| layout.get(c).map_or(0.0, LoudnessChannel::weight))
+                .collect(),
+            None => vec![1.0; channels],
+        };
+        let fs = sample_rate as f64;
+
+        Ok(LoudnessMeter {
+            channels,
+            weights,
+            filters: vec![[Biquad::pre_filter(fs), Biquad::rlb_filter(fs)]; channels],
+            step_frames: (fs * STEP_SECONDS) as usize,
+            frames_in_step: 0,
+            step_energy: vec![0.0; channels],
+            steps: VecDeque::with_capacity(SHORT_TERM_STEPS),
+            momentary_blocks: Vec::new(),
+            short_term_blocks: Vec::new(),
+        })
+    }
+
+    /// Discards the integrated loudness and loudness range measured so far.
+    pub fn reset(&mut self) {
+        self.momentary_blocks.clear();
+        self.short_term_blocks.clear();
+    }
+
+    /// Returns a frame for every 100 ms step completed by the samples.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<LoudnessFrame> {
+        let mut frames = Vec::new();
+
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let [pre, rlb] = &mut self.filters[channel];
+                let filtered = rlb.process(pre.process(*sample as f64));
+                self.step_energy[channel] += filtered * filtered;
+            }
+
+            self.frames_in_step += 1;
+            if self.frames_in_step == self.step_frames {
+                frames.push(self.step());
+            }
+        }
+
+        frames
+    }
+
+    fn step(&mut self) -> LoudnessFrame {
+        let energy = self
+            .step_energy
+            .iter()
+            .zip(&self.weights)
+            .map(|(energy, weight)| energy * weight)
+            .sum::<f64>()
+            / self.frames_in_step as f64;
+        self.step_energy.iter_mut().for_each(|e| *e = 0.0);
+        self.frames_in_step = 0;
+
+        if self.steps.len() == SHORT_TERM_STEPS {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(energy);
+
+        let mean = |steps: usize| {
+            (self.steps.len() >= steps)
+                .then(|| self.steps.iter().rev().take(steps).sum::<f64>() / steps as f64)
+        };
+        let momentary = mean(MOMENTARY_STEPS);
+        let short_term = mean(SHORT_TERM_STEPS);
+        if let Some(momentary) = momentary {
+            self.momentary_blocks.push(momentary);
+        }
+        if let Some(short_term) = short_term {
+            self.short_term_blocks.push(short_term);
+        }
+
+        LoudnessFrame {
+            momentary: momentary.map(loudness),
+            short_term: short_term.map(loudness),
+            integrated: integrated(&self.momentary_blocks).map(|l| l as f32),
+            loudness_range: loudness_range(&self.short_term_blocks).map(|l| l as f32),
+        }
+    }
+}
+
+fn loudness(mean_square: f64) -> f32 {
+    to_lufs(mean_square).max(f32::MIN as f64) as f32
+}
+
+fn to_lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+fn to_mean_square(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+/// Applies the absolute gate and the given relative gate to the blocks.
+fn gate(blocks: &[f64], relative_gate: f64) -> Vec<f64> {
+    let absolute_gate = to_mean_square(ABSOLUTE_GATE);
+    let above_absolute: Vec<f64> = blocks
+        .iter()
+        .copied()
+        .filter(|b| *b > absolute_gate)
+        .collect();
+    if above_absolute.is_empty() {
+        return above_absolute;
+    }
+    let mean = above_absolute.iter().sum::<f64>() / above_absolute.len() as f64;
+    let threshold = to_mean_square(to_lufs(mean) + relative_gate);
+    above_absolute
+        .into_iter()
+        .filter(|b| *b > threshold)
+        .collect()
+}
+
+fn integrated(blocks: &[f64]) -> Option<f64> {
+    let gated = gate(blocks, INTEGRATED_RELATIVE_GATE);
+    (!gated.is_empty()).then(|| to_lufs(gated.iter().sum::<f64>() / gated.len() as f64))
+}
+
+fn loudness_range(blocks: &[f64]) -> Option<f64> {
+    let mut gated: Vec<f64> = gate(blocks, RANGE_RELATIVE_GATE)
+        .into_iter()
+        .map(to_lufs)
+        .collect();
+    if gated.is_empty() {
+        return None;
+    }
+    gated.sort_by(f64::total_cmp);
+    let percentile = |p: f64| gated[((gated.len() - 1) as f64 * p).round() as usize];
+    Some(percentile(0.95) - percentile(0.10))
+}
+
+/// Filters of the K-weighting curve, with coefficients derived for any sample rate.
+#[derive(Debug, Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    /// High shelf modelling the acoustic effect of the head.
+    fn pre_filter(fs: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / fs).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    /// Revised low-frequency B-weighting high pass.
+    fn rlb_filter(fs: f64) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / fs).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sine(level: f64, seconds: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(level / 20.0);
+        (0..(48_000.0 * seconds) as usize)
+            .flat_map(|i| {
+                let v = (amplitude * (2.0 * PI * 1_000.0 * i as f64 / 48_000.0).sin()) as f32;
+                [v, v]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stereo_sine_reference_level() {
+        // EBU Tech 3341 test case 1
+        let mut meter = LoudnessMeter::new(2, 48_000, &LoudnessConfig::default()).unwrap();
+        let frame = meter.process(&sine(-23.0, 20.0)).pop().unwrap();
+        for value in [frame.momentary, frame.short_term, frame.integrated] {
+            assert!((value.unwrap() + 23.0).abs() < 0.1, "{frame:?}");
+        }
+    }
+
+    #[test]
+    fn loudness_range_and_reset() {
+        // EBU Tech 3342 test case 1
+        let mut meter = LoudnessMeter::new(2, 48_000, &LoudnessConfig::default()).unwrap();
+        meter.process(&sine(-20.0, 20.0));
+        let frame = meter.process(&sine(-30.0, 20.0)).pop().unwrap();
+        assert!(
+            (frame.loudness_range.unwrap() - 10.0).abs() < 1.0,
+            "{frame:?}"
+        );
+
+        meter.reset();
+        let frame = meter.process(&sine(-30.0, 5.0)).pop().unwrap();
+        assert!((frame.integrated.unwrap() + 30.0).abs() < 0.1, "{frame:?}");
+    }
+}
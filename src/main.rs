@@ -1,8 +1,38 @@
-use aes67_to_ws::poem::{self};
+use aes67_to_ws::{
+    config::Config,
+    poem::{self},
+};
+use std::future;
+use tokio::{select, signal};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    dotenv::dotenv().ok();
     env_logger::init();
 
-    poem::start().await
+    let config = Config::load()?;
+    poem::start(config, shutdown_signal()).await
+}
+
+/// Completes on SIGTERM or Ctrl-C.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                log::error!("Cannot handle SIGTERM: {e}");
+                future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
+
+    select! {
+        _ = signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
 }
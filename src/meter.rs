@@ -0,0 +1,297 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+/// Levels below this are reported as this, as JSON has no representation of -inf.
+pub const MIN_LEVEL_DB: f32 = -120.0;
+/// Oversampling factor of the true-peak measurement, as specified by ITU-R BS.1770.
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+const TRUE_PEAK_TAPS: usize = 12;
+const MAX_RATE: f32 = 100.0;
+const MAX_RMS_WINDOW: f32 = 10.0;
+/// Upper bound of the samples kept for the RMS window across all channels, 64 MB.
+const MAX_RMS_SAMPLES: usize = 1 << 24;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MeterFormat {
+    Json,
+    /// Little endian f32 levels in dBFS: all channels' peaks, then RMS, then true peaks if
+    /// enabled.
+    Binary,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeterConfig {
+    /// Meter frames per second of audio.
+    #[serde(default = "default_rate")]
+    pub rate: f32,
+    /// Integration time of the RMS level in seconds.
+    #[serde(default = "default_rms_window")]
+    pub rms_window: f32,
+    #[serde(default)]
+    pub true_peak: bool,
+    #[serde(default = "default_format")]
+    pub format: MeterFormat,
+}
+
+fn default_rate() -> f32 {
+    30.0
+}
+
+fn default_rms_window() -> f32 {
+    0.3
+}
+
+fn default_format() -> MeterFormat {
+    MeterFormat::Json
+}
+
+/// Levels per channel in dBFS. Peaks are the maximum since the previous frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeterFrame {
+    pub peak: Vec<f32>,
+    pub rms: Vec<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub true_peak: Option<Vec<f32>>,
+}
+
+impl MeterFrame {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.peak
+            .iter()
+            .chain(&self.rms)
+            .chain(self.true_peak.iter().flatten())
+            .flat_map(|level| level.to_le_bytes())
+            .collect()
+    }
+}
+
+/// Computes meter frames from interleaved, normalized samples.
+pub struct Meter {
+    channels: usize,
+    frames_per_update: usize,
+    frames_since_update: usize,
+    peak: Vec<f32>,
+    squares: Vec<f64>,
+    square_sums: Vec<f64>,
+    rms_window: usize,
+    rms_position: usize,
+    true_peak: Option<TruePeak>,
+}
+
+impl Meter {
+    pub fn new(channels: u16, sample_rate: u32, config: &MeterConfig) -> anyhow::Result<Self> {
+        if channels == 0 || sample_rate == 0 {
+            return Err(anyhow!("invalid meter format"));
+        }
+        if !(config.rate > 0.0 && config.rate <= MAX_RATE) {
+            return Err(anyhow!("meter rate must be above 0 and at most {MAX_RATE}"));
+        }
+        if !(config.rms_window > 0.0 && config.rms_window <= MAX_RMS_WINDOW) {
+            return Err(anyhow!(
+                "RMS window must be above 0 and at most {MAX_RMS_WINDOW} seconds"
+            ));
+        }
+        let channels = channels as usize;
+        let rms_window = ((config.rms_window as f64 * sample_rate as f64) as usize).max(1);
+        if rms_window.saturating_mul(channels) > MAX_RMS_SAMPLES {
+            return Err(anyhow!("RMS window is too long for {channels} channels"));
+        }
+
+        Ok(Meter {
+            channels,
+            frames_per_update: ((sample_rate as f32 / config.rate) as usize).max(1),
+            frames_since_update: 0,
+            peak: vec![0.0; channels],
+            squares: vec![0.0; rms_window * channels],
+            square_sums: vec![0.0; channels],
+            rms_window,
+            rms_position: 0,
+            true_peak: config.true_peak.then(|| TruePeak::new(channels)),
+        })
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Vec<MeterFrame> {
+        let mut frames = Vec::new();
+
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                self.peak[channel] = self.peak[channel].max(sample.abs());
+
+                let square = *sample as f64 * *sample as f64;
+                let slot = &mut self.squares[self.rms_position * self.channels + channel];
+                self.square_sums[channel] += square - *slot;
+                *slot = square;
+            }
+            self.rms_position = (self.rms_position + 1) % self.rms_window;
+            if self.rms_position == 0 {
+                // avoid accumulating rounding errors in the running sums
+                for (channel, sum) in self.square_sums.iter_mut().enumerate() {
+                    *sum = self
+                        .squares
+                        .iter()
+                        .skip(channel)
+                        .step_by(self.channels)
+                        .sum();
+                }
+            }
+            if let Some(true_peak) = &mut self.true_peak {
+                true_peak.process(frame);
+            }
+
+            self.frames_since_update += 1;
+            if self.frames_since_update == self.frames_per_update {
+                self.frames_since_update = 0;
+                frames.push(self.frame());
+            }
+        }
+
+        frames
+    }
+
+    fn frame(&mut self) -> MeterFrame {
+        let frame = MeterFrame {
+            peak: self.peak.iter().map(|p| to_db(*p as f64)).collect(),
+            rms: self
+                .square_sums
+                .iter()
+                .map(|sum| to_db((sum.max(0.0) / self.rms_window as f64).sqrt()))
+                .collect(),
+            true_peak: self
+                .true_peak
+                .as_mut()
+                .map(|t| t.take().into_iter().map(|p| to_db(p as f64)).collect()),
+        };
+        self.peak.iter_mut().for_each(|p| *p = 0.0);
+        frame
+    }
+}
+
+fn to_db(level: f64) -> f32 {
+    (20.0 * level.log10()).max(MIN_LEVEL_DB as f64) as f32
+}
+
+/// Peak of the signal reconstructed by 4x oversampling.
+struct TruePeak {
+    channels: usize,
+    phases: [[f32; TRUE_PEAK_TAPS]; TRUE_PEAK_OVERSAMPLING],
+    history: Vec<f32>,
+    position: usize,
+    peak: Vec<f32>,
+}
+
+impl TruePeak {
+    fn new(channels: usize) -> Self {
+        let mut phases = [[0.0; TRUE_PEAK_TAPS]; TRUE_PEAK_OVERSAMPLING];
+        let half = TRUE_PEAK_TAPS as f64 / 2.0;
+        for (phase, coefficients) in phases.iter_mut().enumerate() {
+            for (k, c) in coefficients.iter_mut().enumerate() {
+                // distance of the interpolated point from input sample k
+                let t = phase as f64 / TRUE_PEAK_OVERSAMPLING as f64 + half - 1.0 - k as f64;
+                let sinc = if t == 0.0 {
+                    1.0
+                } else {
+                    (PI * t).sin() / (PI * t)
+                };
+                let window = 0.5 + 0.5 * (PI * t / half).cos();
+                *c = (sinc * window) as f32;
+            }
+        }
+
+        TruePeak {
+            channels,
+            phases,
+            history: vec![0.0; TRUE_PEAK_TAPS * channels],
+            position: 0,
+            peak: vec![0.0; channels],
+        }
+    }
+
+    fn process(&mut self, frame: &[f32]) {
+        for (channel, sample) in frame.iter().enumerate() {
+            self.history[self.position * self.channels + channel] = *sample;
+        }
+        self.position = (self.position + 1) % TRUE_PEAK_TAPS;
+
+        for channel in 0..self.channels {
+            for coefficients in &self.phases {
+                let value: f32 = coefficients
+                    .iter()
+                    .enumerate()
+                    .map(|(k, c)| {
+                        let index = (self.position + k) % TRUE_PEAK_TAPS;
+                        self.history[index * self.channels + channel] * c
+                    })
+                    .sum();
+                self.peak[channel] = self.peak[channel].max(value.abs());
+            }
+        }
+    }
+
+    fn take(&mut self) -> Vec<f32> {
+        std::mem::replace(&mut self.peak, vec![0.0; self.channels])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(true_peak: bool) -> MeterConfig {
+        MeterConfig {
+            rate: 10.0,
+            rms_window: 0.1,
+            true_peak,
+            format: MeterFormat::Json,
+        }
+    }
+
+    #[test]
+    fn reject_excessive_config() {
+        for (rate, rms_window) in [(10.0, 1e9), (10.0, 0.0), (1e6, 0.3), (f32::NAN, 0.3)] {
+            let config = MeterConfig {
+                rate,
+                rms_window,
+                ..config(false)
+            };
+            assert!(Meter::new(2, 48_000, &config).is_err(), "{config:?}");
+        }
+        assert!(Meter::new(u16::MAX, 192_000, &config(false)).is_err());
+    }
+
+    #[test]
+    fn sine_levels() {
+        let mut meter = Meter::new(2, 48_000, &config(false)).unwrap();
+        let samples: Vec<f32> = (0..48_000)
+            .flat_map(|i| {
+                let v = (2.0 * PI * 1_000.0 * i as f64 / 48_000.0).sin() as f32 * 0.5;
+                [v, 0.0]
+            })
+            .collect();
+        let frames = meter.process(&samples);
+        assert_eq!(frames.len(), 10);
+
+        let last = frames.last().unwrap();
+        assert!((last.peak[0] + 6.02).abs() < 0.01, "{last:?}");
+        assert!((last.rms[0] + 9.03).abs() < 0.01, "{last:?}");
+        assert_eq!(last.peak[1], MIN_LEVEL_DB);
+        assert_eq!(last.true_peak, None);
+        assert_eq!(last.to_bytes().len(), 4 * 4);
+    }
+
+    #[test]
+    fn true_peak_between_samples() {
+        let mut meter = Meter::new(1, 48_000, &config(true)).unwrap();
+        // a quarter sample rate sine sampled at 45 degrees never hits its peak
+        let samples: Vec<f32> = (0..4_800)
+            .map(|i| (PI / 2.0 * i as f64 + PI / 4.0).sin() as f32)
+            .collect();
+        let frame = meter.process(&samples).pop().unwrap();
+        assert!((frame.peak[0] + 3.01).abs() < 0.01, "{frame:?}");
+        let true_peak = frame.true_peak.unwrap()[0];
+        assert!(true_peak.abs() < 0.2, "{true_peak}");
+    }
+}
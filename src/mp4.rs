@@ -0,0 +1,212 @@
+use crate::flac::StreamInfo;
+
+const MOVIE_TIMESCALE: u32 = 1_000;
+const TRACK_ID: u32 = 1;
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+const TRUN_DATA_OFFSET_OFFSET: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub duration: u32,
+    pub data: Vec<u8>,
+}
+
+/// Builds the initialization segment (ftyp + moov) for a single fragmented FLAC audio track.
+pub fn init_segment(info: &StreamInfo) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    write_box(&mut buf, b"ftyp", |b| {
+        b.extend_from_slice(b"iso6");
+        b.extend_from_slice(&0u32.to_be_bytes());
+        b.extend_from_slice(b"iso6");
+        b.extend_from_slice(b"mp41");
+    });
+
+    write_box(&mut buf, b"moov", |b| {
+        write_full_box(b, b"mvhd", 0, 0, |b| {
+            put_u32(b, 0);
+            put_u32(b, 0);
+            put_u32(b, MOVIE_TIMESCALE);
+            put_u32(b, 0);
+            put_u32(b, 0x0001_0000);
+            put_u16(b, 0x0100);
+            b.extend_from_slice(&[0; 10]);
+            UNITY_MATRIX.iter().for_each(|v| put_u32(b, *v));
+            b.extend_from_slice(&[0; 24]);
+            put_u32(b, TRACK_ID + 1);
+        });
+        write_box(b, b"trak", |b| {
+            write_full_box(b, b"tkhd", 0, 0x000003, |b| {
+                put_u32(b, 0);
+                put_u32(b, 0);
+                put_u32(b, TRACK_ID);
+                put_u32(b, 0);
+                put_u32(b, 0);
+                b.extend_from_slice(&[0; 8]);
+                put_u16(b, 0);
+                put_u16(b, 0);
+                put_u16(b, 0x0100);
+                put_u16(b, 0);
+                UNITY_MATRIX.iter().for_each(|v| put_u32(b, *v));
+                put_u32(b, 0);
+                put_u32(b, 0);
+            });
+            write_box(b, b"mdia", |b| {
+                write_full_box(b, b"mdhd", 0, 0, |b| {
+                    put_u32(b, 0);
+                    put_u32(b, 0);
+                    put_u32(b, info.sample_rate);
+                    put_u32(b, 0);
+                    // packed ISO-639-2 language code 'und'
+                    put_u16(b, 0x55C4);
+                    put_u16(b, 0);
+                });
+                write_full_box(b, b"hdlr", 0, 0, |b| {
+                    put_u32(b, 0);
+                    b.extend_from_slice(b"soun");
+                    b.extend_from_slice(&[0; 12]);
+                    b.extend_from_slice(b"SoundHandler\0");
+                });
+                write_box(b, b"minf", |b| {
+                    write_full_box(b, b"smhd", 0, 0, |b| {
+                        put_u16(b, 0);
+                        put_u16(b, 0);
+                    });
+                    write_box(b, b"dinf", |b| {
+                        write_full_box(b, b"dref", 0, 0, |b| {
+                            put_u32(b, 1);
+                            write_full_box(b, b"url ", 0, 0x000001, |_| {});
+                        });
+                    });
+                    write_box(b, b"stbl", |b| {
+                        write_full_box(b, b"stsd", 0, 0, |b| {
+                            put_u32(b, 1);
+                            write_flac_sample_entry(b, info);
+                        });
+                        write_full_box(b, b"stts", 0, 0, |b| put_u32(b, 0));
+                        write_full_box(b, b"stsc", 0, 0, |b| put_u32(b, 0));
+                        write_full_box(b, b"stsz", 0, 0, |b| {
+                            put_u32(b, 0);
+                            put_u32(b, 0);
+                        });
+                        write_full_box(b, b"stco", 0, 0, |b| put_u32(b, 0));
+                    });
+                });
+            });
+        });
+        write_box(b, b"mvex", |b| {
+            write_full_box(b, b"trex", 0, 0, |b| {
+                put_u32(b, TRACK_ID);
+                put_u32(b, 1);
+                put_u32(b, 0);
+                put_u32(b, 0);
+                put_u32(b, 0);
+            });
+        });
+    });
+
+    buf
+}
+
+/// Builds a media segment (moof + mdat) containing the given samples, starting at the given
+/// decode time in units of the track's sample rate.
+pub fn media_segment(
+    sequence_number: u32,
+    base_media_decode_time: u64,
+    samples: &[Sample],
+) -> Vec<u8> {
+    let mut moof = Vec::new();
+    let mut trun_position = 0;
+
+    write_box(&mut moof, b"moof", |b| {
+        write_full_box(b, b"mfhd", 0, 0, |b| put_u32(b, sequence_number));
+        write_box(b, b"traf", |b| {
+            // default-base-is-moof
+            write_full_box(b, b"tfhd", 0, 0x020000, |b| put_u32(b, TRACK_ID));
+            write_full_box(b, b"tfdt", 1, 0, |b| put_u64(b, base_media_decode_time));
+            trun_position = b.len();
+            // data-offset-present, sample-duration-present, sample-size-present
+            write_full_box(b, b"trun", 0, 0x000301, |b| {
+                put_u32(b, samples.len() as u32);
+                put_u32(b, 0);
+                for sample in samples {
+                    put_u32(b, sample.duration);
+                    put_u32(b, sample.data.len() as u32);
+                }
+            });
+        });
+    });
+
+    let data_offset_position = trun_position + TRUN_DATA_OFFSET_OFFSET;
+    let data_offset = (moof.len() + 8) as u32;
+    moof[data_offset_position..data_offset_position + 4]
+        .copy_from_slice(&data_offset.to_be_bytes());
+
+    write_box(&mut moof, b"mdat", |b| {
+        for sample in samples {
+            b.extend_from_slice(&sample.data);
+        }
+    });
+
+    moof
+}
+
+fn write_flac_sample_entry(buf: &mut Vec<u8>, info: &StreamInfo) {
+    write_box(buf, b"fLaC", |b| {
+        b.extend_from_slice(&[0; 6]);
+        put_u16(b, 1);
+        b.extend_from_slice(&[0; 8]);
+        put_u16(b, info.channels);
+        put_u16(b, info.bits_per_sample);
+        put_u16(b, 0);
+        put_u16(b, 0);
+        // rates that do not fit the 16.16 fixed point field are signalled in STREAMINFO only
+        let sample_rate = if info.sample_rate <= u16::MAX as u32 {
+            info.sample_rate << 16
+        } else {
+            0
+        };
+        put_u32(b, sample_rate);
+        write_full_box(b, b"dfLa", 0, 0, |b| {
+            let streaminfo = info.to_bytes();
+            // last-metadata-block flag set, block type 0 (STREAMINFO)
+            b.push(0x80);
+            b.extend_from_slice(&(streaminfo.len() as u32).to_be_bytes()[1..]);
+            b.extend_from_slice(&streaminfo);
+        });
+    });
+}
+
+fn write_box(buf: &mut Vec<u8>, kind: &[u8; 4], body: impl FnOnce(&mut Vec<u8>)) {
+    let start = buf.len();
+    put_u32(buf, 0);
+    buf.extend_from_slice(kind);
+    body(buf);
+    let size = (buf.len() - start) as u32;
+    buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box(
+    buf: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    body: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(buf, kind, |b| {
+        put_u32(b, (version as u32) << 24 | (flags & 0x00FF_FFFF));
+        body(b);
+    });
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
@@ -0,0 +1,426 @@
+use anyhow::anyhow;
+use std::{
+    io::{ErrorKind, Read},
+    net::{Ipv4Addr, SocketAddrV4},
+    time::Duration,
+};
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x0000_0006;
+const PCAPNG_OPTION_END: u16 = 0;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub timestamp: Duration,
+    pub link_type: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Interface {
+    link_type: u16,
+    /// Duration of one timestamp unit in nanoseconds.
+    resolution: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Format {
+    Pcap {
+        big_endian: bool,
+        nanos: bool,
+        link_type: u16,
+    },
+    PcapNg {
+        big_endian: bool,
+        interfaces: Vec<Interface>,
+    },
+}
+
+/// Reads link layer frames from a pcap or pcapng capture.
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    format: Format,
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        let format = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (PCAPNG_SECTION_HEADER, _) => {
+                let mut format = Format::PcapNg {
+                    big_endian: false,
+                    interfaces: Vec::new(),
+                };
+                read_section_header(&mut reader, &mut format)?;
+                format
+            }
+            (m, _) if m == PCAP_MAGIC_MICROS || m == PCAP_MAGIC_NANOS => {
+                read_pcap_header(&mut reader, false, m == PCAP_MAGIC_NANOS)?
+            }
+            (_, m) if m == PCAP_MAGIC_MICROS || m == PCAP_MAGIC_NANOS => {
+                read_pcap_header(&mut reader, true, m == PCAP_MAGIC_NANOS)?
+            }
+            _ => return Err(anyhow!("not a pcap or pcapng file")),
+        };
+
+        Ok(CaptureReader { reader, format })
+    }
+
+    /// Returns the next captured frame or `None` once the end of the capture is reached.
+    pub fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        match &self.format {
+            Format::Pcap {
+                big_endian,
+                nanos,
+                link_type,
+            } => {
+                let mut header = [0; 16];
+                if !read_or_eof(&mut self.reader, &mut header)? {
+                    return Ok(None);
+                }
+                let secs = u32_at(&header, 0, *big_endian);
+                let fraction = u32_at(&header, 4, *big_endian);
+                let captured_len = u32_at(&header, 8, *big_endian) as usize;
+                let mut data = vec![0; captured_len];
+                self.reader.read_exact(&mut data)?;
+                let timestamp = Duration::from_secs(secs as u64)
+                    + if *nanos {
+                        Duration::from_nanos(fraction as u64)
+                    } else {
+                        Duration::from_micros(fraction as u64)
+                    };
+                Ok(Some(Frame {
+                    timestamp,
+                    link_type: *link_type,
+                    data,
+                }))
+            }
+            Format::PcapNg { .. } => self.next_pcapng_frame(),
+        }
+    }
+
+    fn next_pcapng_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        loop {
+            let mut header = [0; 8];
+            if !read_or_eof(&mut self.reader, &mut header)? {
+                return Ok(None);
+            }
+
+            if u32::from_le_bytes([header[0], header[1], header[2], header[3]])
+                == PCAPNG_SECTION_HEADER
+            {
+                let mut reader = (&header[4..]).chain(&mut self.reader);
+                read_section_header(&mut reader, &mut self.format)?;
+                continue;
+            }
+
+            let Format::PcapNg {
+                big_endian,
+                interfaces,
+            } = &mut self.format
+            else {
+                unreachable!("only called for pcapng captures");
+            };
+            let block_type = u32_at(&header, 0, *big_endian);
+            let total_len = u32_at(&header, 4, *big_endian) as usize;
+            if total_len < 12 {
+                return Err(anyhow!("malformed pcapng block length: {total_len}"));
+            }
+            let mut body = vec![0; total_len - 8];
+            self.reader.read_exact(&mut body)?;
+            // drop the trailing copy of the block length
+            body.truncate(body.len() - 4);
+
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION => {
+                    interfaces.push(read_interface(&body, *big_endian)?);
+                }
+                PCAPNG_ENHANCED_PACKET => {
+                    if body.len() < 20 {
+                        return Err(anyhow!("truncated enhanced packet block"));
+                    }
+                    let interface_id = u32_at(&body, 0, *big_endian) as usize;
+                    let interface = interfaces
+                        .get(interface_id)
+                        .ok_or_else(|| anyhow!("unknown pcapng interface {interface_id}"))?;
+                    let ticks = ((u32_at(&body, 4, *big_endian) as u64) << 32)
+                        | u32_at(&body, 8, *big_endian) as u64;
+                    let captured_len = u32_at(&body, 12, *big_endian) as usize;
+                    let data = body
+                        .get(20..20 + captured_len)
+                        .ok_or_else(|| anyhow!("truncated enhanced packet block"))?
+                        .to_owned();
+                    let timestamp =
+                        Duration::from_nanos((ticks as f64 * interface.resolution) as u64);
+                    return Ok(Some(Frame {
+                        timestamp,
+                        link_type: interface.link_type,
+                        data,
+                    }));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn read_pcap_header(
+    reader: &mut impl Read,
+    big_endian: bool,
+    nanos: bool,
+) -> anyhow::Result<Format> {
+    let mut header = [0; 20];
+    reader.read_exact(&mut header)?;
+    let link_type = (u32_at(&header, 16, big_endian) & 0xFFFF) as u16;
+    Ok(Format::Pcap {
+        big_endian,
+        nanos,
+        link_type,
+    })
+}
+
+/// Reads the remainder of a section header block after its block type and resets the
+/// interfaces, which are scoped to a section.
+fn read_section_header(reader: &mut impl Read, format: &mut Format) -> anyhow::Result<()> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+    let big_endian = match u32::from_le_bytes([header[4], header[5], header[6], header[7]]) {
+        PCAPNG_BYTE_ORDER_MAGIC => false,
+        m if m.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => true,
+        _ => return Err(anyhow!("malformed pcapng section header")),
+    };
+    let total_len = u32_at(&header, 0, big_endian) as usize;
+    if total_len < 28 {
+        return Err(anyhow!(
+            "malformed pcapng section header length: {total_len}"
+        ));
+    }
+    let mut rest = vec![0; total_len - 12];
+    reader.read_exact(&mut rest)?;
+    *format = Format::PcapNg {
+        big_endian,
+        interfaces: Vec::new(),
+    };
+    Ok(())
+}
+
+fn read_interface(body: &[u8], big_endian: bool) -> anyhow::Result<Interface> {
+    if body.len() < 8 {
+        return Err(anyhow!("truncated interface description block"));
+    }
+    let link_type = u16_at(body, 0, big_endian);
+    let mut resolution = 1_000.0;
+
+    let mut options = &body[8..];
+    while options.len() >= 4 {
+        let code = u16_at(options, 0, big_endian);
+        let len = u16_at(options, 2, big_endian) as usize;
+        if code == PCAPNG_OPTION_END {
+            break;
+        }
+        if code == PCAPNG_OPTION_TSRESOL && len == 1 && options.len() > 4 {
+            let value = options[4];
+            let units_per_second = if value & 0x80 == 0 {
+                10f64.powi(value as i32)
+            } else {
+                2f64.powi((value & 0x7F) as i32)
+            };
+            resolution = 1e9 / units_per_second;
+        }
+        let padded = (len + 3) & !3;
+        options = options.get(4 + padded..).unwrap_or_default();
+    }
+
+    Ok(Interface {
+        link_type,
+        resolution,
+    })
+}
+
+/// Extracts the payload of a UDP datagram sent to the given destination from a link layer
+/// frame. Fragmented datagrams are ignored.
+pub fn udp_payload(frame: &Frame, destination: SocketAddrV4) -> Option<&[u8]> {
+    let ip = match frame.link_type {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16_at(frame.data.get(offset..offset + 2)?, 0, true);
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = u16_at(frame.data.get(offset..offset + 2)?, 0, true);
+            }
+            if ethertype != ETHERTYPE_IPV4 {
+                return None;
+            }
+            frame.data.get(offset + 2..)?
+        }
+        LINKTYPE_LINUX_SLL => {
+            if u16_at(frame.data.get(14..16)?, 0, true) != ETHERTYPE_IPV4 {
+                return None;
+            }
+            frame.data.get(16..)?
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 => &frame.data,
+        _ => return None,
+    };
+
+    if ip.len() < 20 || ip[0] >> 4 != 4 || ip[9] != IP_PROTOCOL_UDP {
+        return None;
+    }
+    let flags_and_offset = u16_at(ip, 6, true);
+    if flags_and_offset & 0x3FFF != 0 {
+        return None;
+    }
+    let header_len = (ip[0] & 0x0F) as usize * 4;
+    let total_len = (u16_at(ip, 2, true) as usize).min(ip.len());
+    let destination_address = Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]);
+    if destination_address != *destination.ip() {
+        return None;
+    }
+
+    let udp = ip.get(header_len..total_len)?;
+    if udp.len() < 8 || u16_at(udp, 2, true) != destination.port() {
+        return None;
+    }
+    let udp_len = (u16_at(udp, 4, true) as usize).clamp(8, udp.len());
+    Some(&udp[8..udp_len])
+}
+
+fn read_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> anyhow::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn u16_at(buf: &[u8], offset: usize, big_endian: bool) -> u16 {
+    let bytes = [buf[offset], buf[offset + 1]];
+    if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    }
+}
+
+fn u32_at(buf: &[u8], offset: usize, big_endian: bool) -> u32 {
+    let bytes = [
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ];
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ethernet_udp_frame(destination: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        let total_len = (20 + 8 + payload.len()) as u16;
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&total_len.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0x40, 0, 64, IP_PROTOCOL_UDP, 0, 0]);
+        frame.extend_from_slice(&[192, 168, 0, 1]);
+        frame.extend_from_slice(&destination.ip().octets());
+        frame.extend_from_slice(&5004u16.to_be_bytes());
+        frame.extend_from_slice(&destination.port().to_be_bytes());
+        frame.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn read_pcap() {
+        let destination = SocketAddrV4::new(Ipv4Addr::new(239, 69, 1, 1), 5004);
+        let frame = ethernet_udp_frame(destination, b"rtp");
+
+        let mut file = Vec::new();
+        file.extend_from_slice(&PCAP_MAGIC_MICROS.to_le_bytes());
+        file.extend_from_slice(&[2, 0, 4, 0]);
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&65535u32.to_le_bytes());
+        file.extend_from_slice(&1u32.to_le_bytes());
+        file.extend_from_slice(&10u32.to_le_bytes());
+        file.extend_from_slice(&500u32.to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(&frame);
+
+        let mut reader = CaptureReader::new(file.as_slice()).unwrap();
+        let frame = reader.next_frame().unwrap().unwrap();
+        assert_eq!(frame.timestamp, Duration::from_micros(10_000_500));
+        assert_eq!(udp_payload(&frame, destination), Some(&b"rtp"[..]));
+        let other = SocketAddrV4::new(Ipv4Addr::new(239, 69, 1, 2), 5004);
+        assert_eq!(udp_payload(&frame, other), None);
+        assert!(reader.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_pcapng() {
+        let destination = SocketAddrV4::new(Ipv4Addr::new(239, 69, 1, 1), 5004);
+        let frame = ethernet_udp_frame(destination, b"rtp!");
+
+        let mut file = Vec::new();
+        file.extend_from_slice(&PCAPNG_SECTION_HEADER.to_le_bytes());
+        file.extend_from_slice(&28u32.to_le_bytes());
+        file.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        file.extend_from_slice(&[1, 0, 0, 0]);
+        file.extend_from_slice(&u64::MAX.to_le_bytes());
+        file.extend_from_slice(&28u32.to_le_bytes());
+
+        file.extend_from_slice(&PCAPNG_INTERFACE_DESCRIPTION.to_le_bytes());
+        file.extend_from_slice(&32u32.to_le_bytes());
+        file.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        file.extend_from_slice(&[0, 0]);
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&PCAPNG_OPTION_TSRESOL.to_le_bytes());
+        file.extend_from_slice(&1u16.to_le_bytes());
+        file.extend_from_slice(&[9, 0, 0, 0]);
+        file.extend_from_slice(&[0; 4]);
+        file.extend_from_slice(&32u32.to_le_bytes());
+
+        let padded = (frame.len() + 3) & !3;
+        let block_len = 32 + padded as u32;
+        file.extend_from_slice(&PCAPNG_ENHANCED_PACKET.to_le_bytes());
+        file.extend_from_slice(&block_len.to_le_bytes());
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&1_500_000_000u32.to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(&frame);
+        file.resize(file.len() + padded - frame.len(), 0);
+        file.extend_from_slice(&block_len.to_le_bytes());
+
+        let mut reader = CaptureReader::new(file.as_slice()).unwrap();
+        let frame = reader.next_frame().unwrap().unwrap();
+        assert_eq!(frame.timestamp, Duration::from_millis(1_500));
+        assert_eq!(udp_payload(&frame, destination), Some(&b"rtp!"[..]));
+        assert!(reader.next_frame().unwrap().is_none());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{am824, BitDepth};
+
+/// Decodes an interleaved RTP audio payload into normalized samples in the range [-1.0, 1.0).
+pub fn decode(payload: &[u8], bit_depth: &BitDepth) -> Vec<f32> {
+    match bit_depth {
+        BitDepth::L8 => payload
+            .iter()
+            .map(|s| (*s as i32 - 128) as f32 / 128.0)
+            .collect(),
+        BitDepth::L16 => payload
+            .chunks_exact(2)
+            .map(|s| i16::from_be_bytes([s[0], s[1]]) as f32 / 32_768.0)
+            .collect(),
+        BitDepth::L20 => {
+            let samples = payload.len() * 8 / 20;
+            (0..samples)
+                .map(|i| {
+                    let s = &payload[i * 5 / 2..];
+                    let bits = if i % 2 == 0 {
+                        i32::from_be_bytes([s[0], s[1], s[2], 0])
+                    } else {
+                        i32::from_be_bytes([s[0], s[1], s[2], 0]) << 4
+                    };
+                    (bits >> 12) as f32 / 524_288.0
+                })
+                .collect()
+        }
+        BitDepth::L24 => payload
+            .chunks_exact(3)
+            .map(|s| (i32::from_be_bytes([s[0], s[1], s[2], 0]) >> 8) as f32 / 8_388_608.0)
+            .collect(),
+        BitDepth::L32 => payload
+            .chunks_exact(4)
+            .map(|s| i32::from_be_bytes([s[0], s[1], s[2], s[3]]) as f32 / 2_147_483_648.0)
+            .collect(),
+        BitDepth::F32 => payload
+            .chunks_exact(4)
+            .map(|s| f32::from_be_bytes([s[0], s[1], s[2], s[3]]))
+            .collect(),
+        BitDepth::F32LE => payload
+            .chunks_exact(4)
+            .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+            .collect(),
+        BitDepth::F64 => payload
+            .chunks_exact(8)
+            .map(|s| f64::from_be_bytes(s.try_into().expect("chunk of 8 bytes")) as f32)
+            .collect(),
+        BitDepth::F64LE => payload
+            .chunks_exact(8)
+            .map(|s| f64::from_le_bytes(s.try_into().expect("chunk of 8 bytes")) as f32)
+            .collect(),
+        BitDepth::AM824 => decode(&am824::audio(payload), &BitDepth::L24),
+    }
+}
+
+/// Encodes normalized samples into an interleaved RTP audio payload.
+pub fn encode(samples: &[f32], bit_depth: &BitDepth) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(bit_depth.bytes(samples.len()));
+    if *bit_depth == BitDepth::L20 {
+        for pair in samples.chunks(2) {
+            let first = quantize(pair[0], 20) as u32 & 0xF_FFFF;
+            let second = pair
+                .get(1)
+                .map_or(0, |s| quantize(*s, 20) as u32 & 0xF_FFFF);
+            let bytes = ((first as u64) << 20 | second as u64).to_be_bytes();
+            // an odd sample at the end only takes up the bytes it needs
+            payload.extend_from_slice(&bytes[3..3 + if pair.len() == 2 { 5 } else { 3 }]);
+        }
+        return payload;
+    }
+    for sample in samples {
+        match bit_depth {
+            BitDepth::L8 => payload.push((quantize(*sample, 8) + 128) as u8),
+            BitDepth::L16 => {
+                payload.extend_from_slice(&(quantize(*sample, 16) as i16).to_be_bytes())
+            }
+            BitDepth::L20 => unreachable!("packed above"),
+            BitDepth::L24 => payload.extend_from_slice(&quantize(*sample, 24).to_be_bytes()[1..]),
+            BitDepth::L32 => payload.extend_from_slice(&quantize(*sample, 32).to_be_bytes()),
+            BitDepth::F32 => payload.extend_from_slice(&sample.to_be_bytes()),
+            BitDepth::F32LE => payload.extend_from_slice(&sample.to_le_bytes()),
+            BitDepth::F64 => payload.extend_from_slice(&(*sample as f64).to_be_bytes()),
+            BitDepth::F64LE => payload.extend_from_slice(&(*sample as f64).to_le_bytes()),
+            // an empty label: valid audio without channel status
+            BitDepth::AM824 => {
+                payload.push(0);
+                payload.extend_from_slice(&quantize(*sample, 24).to_be_bytes()[1..])
+            }
+        }
+    }
+    payload
+}
+
+/// Converts a payload to another encoding, losslessly if the target has sufficient precision.
+pub fn convert(payload: &[u8], from: &BitDepth, to: &BitDepth) -> Vec<u8> {
+    match (from, to) {
+        _ if from == to => payload.to_owned(),
+        (BitDepth::AM824, BitDepth::L24) => am824::audio(payload),
+        (BitDepth::F32LE, BitDepth::F32) | (BitDepth::F64LE, BitDepth::F64) => {
+            let mut payload = payload.to_owned();
+            for sample in payload.chunks_exact_mut((from.bits() / 8) as usize) {
+                sample.reverse();
+            }
+            payload
+        }
+        _ => encode(&decode(payload, from), to),
+    }
+}
+
+/// Quantizes a normalized sample to a signed integer of the given bit width.
+pub fn quantize(sample: f32, bits: u16) -> i32 {
+    let max = (1i64 << (bits - 1)) as f64;
+    let value = (sample as f64 * max).round();
+    value.clamp(-max, max - 1.0) as i32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_l16() {
+        let payload = [0x40, 0x00, 0xC0, 0x00];
+        assert_eq!(decode(&payload, &BitDepth::L16), vec![0.5, -0.5]);
+    }
+
+    #[test]
+    fn decode_l24() {
+        let payload = [0x40, 0x00, 0x00, 0xFF, 0xFF, 0xFF];
+        assert_eq!(
+            decode(&payload, &BitDepth::L24),
+            vec![0.5, -1.0 / 8_388_608.0]
+        );
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        let samples = [0.5, -0.25, 0.0, -1.0];
+        for bit_depth in [
+            BitDepth::L8,
+            BitDepth::L16,
+            BitDepth::L20,
+            BitDepth::L24,
+            BitDepth::L32,
+            BitDepth::F32,
+            BitDepth::F32LE,
+            BitDepth::F64,
+            BitDepth::F64LE,
+            BitDepth::AM824,
+        ] {
+            let payload = encode(&samples, &bit_depth);
+            assert_eq!(payload.len(), bit_depth.bytes(samples.len()));
+            assert_eq!(decode(&payload, &bit_depth), samples, "{bit_depth}");
+            assert_eq!(
+                convert(&payload, &bit_depth, &bit_depth.pcm()),
+                encode(&samples, &bit_depth.pcm()),
+                "{bit_depth}"
+            );
+        }
+    }
+
+    #[test]
+    fn decode_l8() {
+        assert_eq!(
+            decode(&[0x80, 0xC0, 0x00], &BitDepth::L8),
+            vec![0.0, 0.5, -1.0]
+        );
+    }
+
+    #[test]
+    fn pack_l20() {
+        let payload = [0x40, 0x00, 0x0F, 0xFF, 0xFF, 0xC0, 0x00, 0x00];
+        let samples = vec![0.5, -1.0 / 524_288.0, -0.5];
+        assert_eq!(decode(&payload, &BitDepth::L20), samples);
+        assert_eq!(encode(&samples, &BitDepth::L20), payload);
+    }
+
+    #[test]
+    fn float_endianness() {
+        let payload = encode(&[1.0], &BitDepth::F32LE);
+        assert_eq!(payload, [0x00, 0x00, 0x80, 0x3F]);
+        assert_eq!(
+            convert(&payload, &BitDepth::F32LE, &BitDepth::F32),
+            [0x3F, 0x80, 0x00, 0x00]
+        );
+        assert_eq!(
+            decode(&[0xBF, 0xF0, 0, 0, 0, 0, 0, 0], &BitDepth::F64),
+            vec![-1.0]
+        );
+    }
+
+    #[test]
+    fn quantize_roundtrip() {
+        let payload = [0x12, 0x34, 0x56];
+        let sample = decode(&payload, &BitDepth::L24)[0];
+        assert_eq!(quantize(sample, 24), 0x123456);
+        assert_eq!(quantize(1.0, 16), i16::MAX as i32);
+        assert_eq!(quantize(-1.0, 16), i16::MIN as i32);
+    }
+}
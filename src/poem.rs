@@ -1,31 +1,204 @@
+use anyhow::{anyhow, Context};
 use futures_util::{stream::StreamExt, SinkExt};
 use poem::{
     get, handler,
-    listener::TcpListener,
-    web::websocket::{Message, WebSocket, WebSocketStream},
-    IntoResponse, Route,
+    http::{StatusCode, Uri},
+    listener::{Listener, TcpListener},
+    post,
+    web::{
+        websocket::{CloseCode, Message, WebSocket, WebSocketStream},
+        Data, Json, Path, RemoteAddr,
+    },
+    Endpoint, EndpointExt, IntoResponse, Request, Response, Route,
 };
 use serde::{Deserialize, Serialize};
 use std::{
-    net::{Ipv4Addr, SocketAddrV4},
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    path::{Component, Path as StdPath, PathBuf},
+    sync::{Arc, Mutex},
     time::Duration,
 };
 use tokio::{
-    spawn,
+    select, spawn,
     sync::{
-        broadcast,
         mpsc::{self, UnboundedSender},
+        watch,
     },
-    time::sleep,
+    task::JoinHandle,
+    time::{interval, interval_at, sleep, timeout, Instant},
+};
+
+use crate::{
+    am824::{ChannelStatus, ChannelStatusDecoder},
+    auth::{Authenticator, Permissions},
+    clients::{Client, ClientRegistry, ClientStats, DisconnectReason, StreamSlot},
+    config::{Config, StaticSession},
+    generator::GeneratorSession,
+    hls::{HlsConfig, HlsRegistry},
+    loudness::{LoudnessConfig, LoudnessFrame, LoudnessMeter},
+    meter::{Meter, MeterConfig, MeterFormat, MeterFrame},
+    pcm,
+    policy::MulticastPolicy,
+    ptp::{PtpMonitor, PtpStatus, SessionClockStatus},
+    recorder::{RecorderConfig, RecorderRegistry, RecordingInfo},
+    resample::Resampler,
+    sap::SessionDirectory,
+    stream::{Packet, ReplaySpeed, Stream, StreamEvent, StreamHandle, StreamStats},
+    transmitter::Transmitter,
+    watchdog::{Alarm, AlarmEvent, AlarmRegistry, Watchdog, WatchdogConfig},
+    BitDepth, PayloadSizeError, SessionDescriptor,
 };
 
-use crate::{stream::Stream, SessionDescriptor};
+/// How often the PTP clock status of played sessions is checked.
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a replaced stream keeps playing while the stream replacing it has not delivered.
+const SWITCH_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a stopped stream may take to acknowledge before it is aborted.
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum ClientMessage {
-    Play(Session),
+    Play(PlayRequest),
     Stop,
+    /// Play a session alongside the others, tagging its frames and messages with the id.
+    /// Subscribing with an id in use replaces its session.
+    Subscribe {
+        id: String,
+        #[serde(flatten)]
+        request: PlayRequest,
+    },
+    Unsubscribe {
+        id: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    StartRecording {
+        name: String,
+        #[serde(flatten)]
+        request: RecordingRequest,
+    },
+    StopRecording(String),
+    /// Start sending the PCM in subsequent binary messages to the session's multicast group.
+    Transmit(SessionDescriptor),
+    StopTransmit,
+    /// Request the statistics of the currently playing stream, or of a subscription.
+    GetStats {
+        id: Option<String>,
+    },
+    GetPtpStatus,
+    /// Restart the integrated loudness and loudness range measurement of the playing stream, or
+    /// of a subscription.
+    ResetLoudness {
+        id: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ServerMessage {
+    Transmitting {
+        sdp: String,
+    },
+    Stats(StreamStats),
+    Meter(MeterFrame),
+    Loudness(LoudnessFrame),
+    /// Sent whenever a channel of an AM824 session announces a different channel status.
+    ChannelStatus(ChannelStatus),
+    PtpStatus(PtpStatus),
+    /// Sent whenever the state of the playing session's reference clock changes.
+    ClockStatus(SessionClockStatus),
+    /// Sent to all clients when an alarm is raised for any stream.
+    AlarmRaised(Alarm),
+    AlarmCleared(Alarm),
+    /// The sender ended the playing stream.
+    StreamEnded {
+        reason: Option<String>,
+    },
+    /// Payloads of the playing stream do not match its session description.
+    PayloadSizeMismatch(PayloadSizeError),
+    Error(String),
+    /// A message concerning a subscription.
+    Subscription {
+        id: String,
+        message: Box<ServerMessage>,
+    },
+}
+
+/// Services shared by all WebSocket connections.
+#[derive(Clone)]
+struct Services {
+    config: Arc<Config>,
+    policy: MulticastPolicy,
+    recorder: RecorderRegistry,
+    ptp: PtpMonitor,
+    alarms: AlarmRegistry,
+    /// Set once the server is shutting down.
+    shutdown: watch::Receiver<bool>,
+}
+
+/// Handle to a stream playing on a WebSocket connection, stopping it when dropped.
+struct Playback {
+    stats: Arc<Mutex<StreamStats>>,
+    control: UnboundedSender<PlaybackControl>,
+    stream: StreamHandle,
+    /// Task sending the packets of the stream to the client.
+    forwarder: JoinHandle<()>,
+    slot: StreamSlot,
+}
+
+impl Playback {
+    /// Stops the stream and waits until its last frame has been queued, so that no frame of
+    /// it follows those of a stream started afterwards.
+    async fn stop(mut self) {
+        if timeout(STOP_TIMEOUT, self.stream.join()).await.is_err() {
+            log::warn!("Stream did not stop in time, aborting it");
+            self.stream.abort().await;
+        }
+        (&mut self.forwarder).await.ok();
+    }
+}
+
+/// Sends the frames and messages of a played stream, tagged with the id of its subscription.
+/// Binary frames of subscriptions start with the length of the id in bytes, followed by the
+/// id.
+#[derive(Clone)]
+struct Output {
+    id: Option<String>,
+    payload_tx: UnboundedSender<Vec<u8>>,
+    server_tx: UnboundedSender<ServerMessage>,
+}
+
+impl Output {
+    fn send_payload(&self, payload: Vec<u8>) -> bool {
+        let frame = match &self.id {
+            Some(id) => {
+                let mut frame = Vec::with_capacity(1 + id.len() + payload.len());
+                frame.push(id.len() as u8);
+                frame.extend_from_slice(id.as_bytes());
+                frame.extend_from_slice(&payload);
+                frame
+            }
+            None => payload,
+        };
+        self.payload_tx.send(frame).is_ok()
+    }
+
+    fn send_message(&self, message: ServerMessage) -> bool {
+        let message = match &self.id {
+            Some(id) => ServerMessage::Subscription {
+                id: id.clone(),
+                message: Box::new(message),
+            },
+            None => message,
+        };
+        self.server_tx.send(message).is_ok()
+    }
+}
+
+enum PlaybackControl {
+    ResetLoudness,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -33,83 +206,1210 @@ pub enum ClientMessage {
 pub enum Session {
     Sdp(String),
     Custom(SessionDescriptor),
+    Capture(CaptureSession),
+    Generator(GeneratorSession),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureSession {
+    /// Capture file, relative to the capture directory.
+    pub file: PathBuf,
+    /// The session to extract from the capture.
+    pub session: Box<Session>,
+    #[serde(default)]
+    pub speed: ReplaySpeed,
+}
+
+impl Session {
+    pub fn descriptor(self) -> anyhow::Result<SessionDescriptor> {
+        let descriptor = match self {
+            Session::Sdp(sdp) => sdp.parse()?,
+            Session::Custom(sd) => sd,
+            Session::Capture(capture) => capture.session.descriptor()?,
+            Session::Generator(generator) => generator.descriptor,
+        };
+        descriptor.validate()?;
+        Ok(descriptor)
+    }
+
+    /// Fails unless the session may be accessed with the given permissions and, if it is
+    /// received from the network, the multicast policy permits it.
+    pub fn authorize(
+        &self,
+        permissions: &Permissions,
+        policy: &MulticastPolicy,
+    ) -> anyhow::Result<()> {
+        let descriptor = self.clone().descriptor()?;
+        permissions.check(&descriptor, &policy.known_sessions())?;
+        if matches!(self, Session::Sdp(_) | Session::Custom(_)) {
+            policy.check_receive(&descriptor)?;
+        }
+        Ok(())
+    }
+
+    pub async fn open(self, config: &Config) -> anyhow::Result<Stream> {
+        match self {
+            Session::Capture(capture) => {
+                let path = capture_path(&config.captures_directory, &capture.file)?;
+                Stream::from_capture(capture.session.descriptor()?, &path, capture.speed)
+            }
+            Session::Generator(generator) => {
+                generator.descriptor.validate()?;
+                Stream::from_generator(generator)
+            }
+            session => {
+                Stream::new(
+                    session.descriptor()?,
+                    config.interfaces.receive,
+                    config.buffers.socket_receive_buffer,
+                )
+                .await
+            }
+        }
+    }
+}
+
+fn capture_path(directory: &StdPath, file: &StdPath) -> anyhow::Result<PathBuf> {
+    if file.components().all(|c| matches!(c, Component::Normal(_))) {
+        Ok(directory.join(file))
+    } else {
+        Err(anyhow!("invalid capture file: {}", file.display()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayRequest {
+    #[serde(flatten)]
+    pub session: Session,
+    /// Prefix every binary frame with the media time of its first sample, in nanoseconds as a
+    /// big endian u64 (`u64::MAX` if the session does not announce its media clock).
+    #[serde(default)]
+    pub media_time: bool,
+    /// Resample the audio to this rate before sending it.
+    #[serde(default)]
+    pub target_sample_rate: Option<u32>,
+    /// Adjust the resampling ratio to the sender's estimated clock drift, so that the audio is
+    /// delivered at exactly the target rate of the local clock.
+    #[serde(default)]
+    pub drift_compensation: bool,
+    /// Send meter frames instead of audio.
+    #[serde(default)]
+    pub meter: Option<MeterConfig>,
+    /// Measure loudness and send loudness frames alongside the audio or meter frames.
+    #[serde(default)]
+    pub loudness: Option<LoudnessConfig>,
+    /// Alarm thresholds, if different from the defaults.
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfig>,
+    /// Send the channel status of AM824 sessions.
+    #[serde(default)]
+    pub channel_status: bool,
+}
+
+impl PlayRequest {
+    /// Bitrate of the audio sent to the client in bits per second, not counting meter frames.
+    pub fn bitrate(&self) -> anyhow::Result<u64> {
+        if self.meter.is_some() {
+            return Ok(0);
+        }
+        let descriptor = self.session.clone().descriptor()?;
+        let sample_rate = self.target_sample_rate.unwrap_or(descriptor.sample_rate);
+        let samples = sample_rate as usize * descriptor.channels as usize;
+        Ok(descriptor.bit_depth.pcm().bytes(samples) as u64 * 8)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HlsRequest {
+    pub session: Session,
+    #[serde(flatten)]
+    pub config: HlsConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingRequest {
+    pub session: Session,
+    #[serde(flatten)]
+    pub config: RecorderConfig,
 }
 
 #[handler]
-async fn ws(ws: WebSocket) -> impl IntoResponse {
+async fn ws(
+    ws: WebSocket,
+    remote_address: &RemoteAddr,
+    Data(permissions): Data<&Permissions>,
+    Data(clients): Data<&ClientRegistry>,
+    Data(services): Data<&Services>,
+) -> impl IntoResponse {
+    let address = remote_address.as_socket_addr().map(|a| a.ip());
+    let client = clients.connect(address, permissions.clone());
+    let services = services.clone();
     ws.protocols(vec!["aes67-to-ws"])
-        .on_upgrade(move |socket| async move {
-            if let Err(e) = serve(socket).await {
+        .on_upgrade(move |mut socket| async move {
+            let client = match client {
+                Ok(client) => client,
+                Err(e) => {
+                    log::warn!("Refusing WS connection: {e}");
+                    let close = Message::close_with(e.close_code(), e.to_string());
+                    socket.send(close).await.ok();
+                    return;
+                }
+            };
+            if let Err(e) = serve(socket, client, services).await {
                 log::error!("Error in WS connection: {e}");
             }
         })
 }
 
-pub async fn start() -> anyhow::Result<()> {
-    let app = Route::new().nest(format!("/ws"), get(ws));
-    poem::Server::new(TcpListener::bind(SocketAddrV4::new(
-        Ipv4Addr::UNSPECIFIED,
-        9999,
-    )))
-    .run(app)
-    .await?;
+#[handler]
+async fn start_hls(
+    Path(name): Path<String>,
+    Json(request): Json<HlsRequest>,
+    Data(config): Data<&Arc<Config>>,
+    Data(permissions): Data<&Permissions>,
+    Data(policy): Data<&MulticastPolicy>,
+    Data(hls): Data<&HlsRegistry>,
+    Data(alarms): Data<&AlarmRegistry>,
+) -> poem::Result<StatusCode> {
+    request
+        .session
+        .authorize(permissions, policy)
+        .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::FORBIDDEN))?;
+    // the packager replaced, if any, must be accessible as well
+    if let Some(descriptor) = hls.descriptor(&name) {
+        permissions
+            .check(&descriptor, &policy.known_sessions())
+            .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::FORBIDDEN))?;
+    }
+    let mut stream = request
+        .session
+        .open(config)
+        .await
+        .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::BAD_REQUEST))?;
+    watch(&mut stream, WatchdogConfig::default(), alarms);
+    hls.start(name, stream, request.config)
+        .await
+        .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::BAD_REQUEST))?;
+    Ok(StatusCode::CREATED)
+}
+
+#[handler]
+async fn stop_hls(
+    Path(name): Path<String>,
+    Data(permissions): Data<&Permissions>,
+    Data(policy): Data<&MulticastPolicy>,
+    Data(hls): Data<&HlsRegistry>,
+) -> poem::Result<StatusCode> {
+    let descriptor = hls
+        .descriptor(&name)
+        .ok_or_else(|| poem::Error::from_status(StatusCode::NOT_FOUND))?;
+    permissions
+        .check(&descriptor, &policy.known_sessions())
+        .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::FORBIDDEN))?;
+    if hls.stop(&name) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+#[handler]
+async fn hls_file(
+    Path((name, file)): Path<(String, String)>,
+    uri: &Uri,
+    Data(permissions): Data<&Permissions>,
+    Data(policy): Data<&MulticastPolicy>,
+    Data(hls): Data<&HlsRegistry>,
+) -> poem::Result<Response> {
+    let descriptor = hls
+        .descriptor(&name)
+        .ok_or_else(|| poem::Error::from_status(StatusCode::NOT_FOUND))?;
+    permissions
+        .check(&descriptor, &policy.known_sessions())
+        .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::FORBIDDEN))?;
+    let response = if file == "playlist.m3u8" {
+        hls.playlist(&name, uri.query().unwrap_or(""))
+            .map(|playlist| {
+                Response::builder()
+                    .content_type("application/vnd.apple.mpegurl")
+                    .body(playlist)
+            })
+    } else if file == "init.mp4" {
+        hls.init_segment(&name)
+            .map(|init| Response::builder().content_type("audio/mp4").body(init))
+    } else if let Some(sequence_number) = file
+        .strip_prefix("segment")
+        .and_then(|f| f.strip_suffix(".m4s"))
+        .and_then(|n| n.parse().ok())
+    {
+        hls.segment(&name, sequence_number)
+            .map(|segment| Response::builder().content_type("audio/mp4").body(segment))
+    } else {
+        None
+    };
+
+    response.ok_or_else(|| poem::Error::from_status(StatusCode::NOT_FOUND))
+}
+
+#[handler]
+async fn start_recording(
+    Path(name): Path<String>,
+    Json(request): Json<RecordingRequest>,
+    Data(config): Data<&Arc<Config>>,
+    Data(permissions): Data<&Permissions>,
+    Data(policy): Data<&MulticastPolicy>,
+    Data(recorder): Data<&RecorderRegistry>,
+    Data(alarms): Data<&AlarmRegistry>,
+) -> poem::Result<StatusCode> {
+    request
+        .session
+        .authorize(permissions, policy)
+        .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::FORBIDDEN))?;
+    let mut stream = request
+        .session
+        .open(config)
+        .await
+        .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::BAD_REQUEST))?;
+    watch(&mut stream, WatchdogConfig::default(), alarms);
+    recorder
+        .start(name, stream, request.config)
+        .await
+        .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::BAD_REQUEST))?;
+    Ok(StatusCode::CREATED)
+}
+
+#[handler]
+async fn stop_recording(
+    Path(name): Path<String>,
+    Data(permissions): Data<&Permissions>,
+    Data(policy): Data<&MulticastPolicy>,
+    Data(recorder): Data<&RecorderRegistry>,
+) -> poem::Result<StatusCode> {
+    let descriptor = recorder
+        .descriptor(&name)
+        .ok_or_else(|| poem::Error::from_status(StatusCode::NOT_FOUND))?;
+    permissions
+        .check(&descriptor, &policy.known_sessions())
+        .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::FORBIDDEN))?;
+    if recorder.stop(&name) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+/// Lists the recordings of the sessions the client may access.
+#[handler]
+async fn list_recordings(
+    Data(permissions): Data<&Permissions>,
+    Data(policy): Data<&MulticastPolicy>,
+    Data(recorder): Data<&RecorderRegistry>,
+) -> Json<Vec<RecordingInfo>> {
+    let known = policy.known_sessions();
+    let mut recordings = recorder.list();
+    recordings.retain(|r| permissions.allows(&r.descriptor, &known));
+    Json(recordings)
+}
+
+#[handler]
+async fn list_alarms(Data(alarms): Data<&AlarmRegistry>) -> Json<Vec<Alarm>> {
+    Json(alarms.list())
+}
+
+#[handler]
+async fn client_stats(Data(clients): Data<&ClientRegistry>) -> Json<ClientStats> {
+    Json(clients.stats())
+}
+
+#[handler]
+async fn list_sessions(Data(directory): Data<&SessionDirectory>) -> Json<Vec<SessionDescriptor>> {
+    Json(directory.sessions())
+}
+
+#[handler]
+async fn ptp_status(Data(ptp): Data<&PtpMonitor>) -> Json<PtpStatus> {
+    Json(ptp.status())
+}
+
+/// Runs the server until `shutdown` completes, then closes all connections and stops all
+/// streams, waiting at most for the configured shutdown timeout.
+pub async fn start(config: Config, shutdown: impl Future<Output = ()>) -> anyhow::Result<()> {
+    let hls = HlsRegistry::default().with_limit(config.limits.max_hls_streams);
+    let ptp = PtpMonitor::start(config.interfaces.ptp);
+    let recorder = RecorderRegistry::new(&config.recordings_directory)
+        .with_limit(config.limits.max_recordings)
+        .with_ptp(ptp.clone());
+    let alarms = AlarmRegistry::default();
+    for session in &config.sessions {
+        start_static_session(session, &config, &hls, &recorder, &alarms).await?;
+    }
+
+    let address = SocketAddr::new(config.bind_address, config.port);
+    let listener = match config.tls.clone() {
+        None => TcpListener::bind(address).boxed(),
+        Some(tls) => {
+            let port = tls.port;
+            let certificates = tls.watch()?;
+            match port {
+                Some(port) => {
+                    let tls_address = SocketAddr::new(config.bind_address, port);
+                    log::info!("Accepting TLS connections on {tls_address}");
+                    TcpListener::bind(address)
+                        .combine(TcpListener::bind(tls_address).rustls(certificates))
+                        .boxed()
+                }
+                None => {
+                    log::info!("Accepting TLS connections on {address}");
+                    TcpListener::bind(address).rustls(certificates).boxed()
+                }
+            }
+        }
+    };
+    let authenticator = Authenticator::new(config.auth.clone());
+    let directory = SessionDirectory::start(config.interfaces.receive);
+    let configured = config
+        .sessions
+        .iter()
+        .map(|s| {
+            let mut descriptor = s.session.clone().descriptor()?;
+            descriptor.session_name = Some(s.name.clone());
+            Ok(descriptor)
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let policy =
+        MulticastPolicy::new(config.policy.clone(), directory.clone()).with_sessions(configured);
+    let clients = ClientRegistry::new(&config.limits);
+    let config = Arc::new(config);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let services = Services {
+        config: config.clone(),
+        policy: policy.clone(),
+        recorder: recorder.clone(),
+        ptp,
+        alarms: alarms.clone(),
+        shutdown: shutdown_rx.clone(),
+    };
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
+    let app = Route::new()
+        .nest("/ws", get(ws))
+        .at("/hls/:name", post(start_hls).delete(stop_hls))
+        .at("/hls/:name/:file", get(hls_file))
+        .at("/recordings", get(list_recordings))
+        .at(
+            "/recordings/:name",
+            post(start_recording).delete(stop_recording),
+        )
+        .at("/sessions", get(list_sessions))
+        .at("/ptp", get(ptp_status))
+        .at("/alarms", get(list_alarms))
+        .at("/clients", get(client_stats))
+        .around(move |endpoint, request| authenticate(endpoint, request, authenticator.clone()))
+        .data(hls.clone())
+        .data(recorder.clone())
+        .data(directory)
+        .data(policy)
+        .data(clients.clone())
+        .data(services.ptp.clone())
+        .data(alarms)
+        .data(services)
+        .data(config);
+    log::info!("Listening on {address}");
+    let mut stopped = shutdown_rx;
+    let mut server = spawn(poem::Server::new(listener).run_with_graceful_shutdown(
+        app,
+        async move {
+            stopped.changed().await.ok();
+        },
+        Some(shutdown_timeout),
+    ));
+    select! {
+        result = &mut server => {
+            result??;
+            return Ok(());
+        }
+        _ = shutdown => {}
+    }
+
+    log::info!("Shutting down");
+    shutdown_tx.send(true).ok();
+    let stopped = timeout(shutdown_timeout, async {
+        match server.await {
+            Ok(Err(e)) => log::error!("Error shutting down server: {e}"),
+            Err(e) => log::error!("Error shutting down server: {e}"),
+            Ok(Ok(())) => {}
+        }
+        clients.disconnected().await;
+        recorder.stop_all().await;
+        hls.stop_all().await;
+    });
+    if stopped.await.is_err() {
+        log::warn!("Shutdown timed out after {shutdown_timeout:?}");
+    }
+    Ok(())
+}
+
+/// Rejects requests without valid credentials and makes the permissions they grant available
+/// to handlers.
+async fn authenticate<E: Endpoint>(
+    endpoint: Arc<E>,
+    mut request: Request,
+    authenticator: Authenticator,
+) -> poem::Result<Response> {
+    let permissions = authenticator.authenticate(&request).map_err(|e| {
+        log::info!("Rejected request for {}: {e:#}", request.uri().path());
+        poem::Error::from_string(format!("{e:#}"), StatusCode::UNAUTHORIZED)
+    })?;
+    request.extensions_mut().insert(permissions);
+    Ok(endpoint.call(request).await?.into_response())
+}
+
+async fn start_static_session(
+    session: &StaticSession,
+    config: &Config,
+    hls: &HlsRegistry,
+    recorder: &RecorderRegistry,
+    alarms: &AlarmRegistry,
+) -> anyhow::Result<()> {
+    let name = &session.name;
+    log::info!("Starting static session '{name}'");
+    let open = || async {
+        let mut stream = session
+            .session
+            .clone()
+            .open(config)
+            .await
+            .with_context(|| format!("cannot open static session '{name}'"))?;
+        watch(&mut stream, WatchdogConfig::default(), alarms);
+        anyhow::Ok(stream)
+    };
+    if let Some(record) = &session.record {
+        recorder
+            .start(name.clone(), open().await?, record.clone())
+            .await
+            .with_context(|| format!("cannot record static session '{name}'"))?;
+    }
+    if let Some(hls_config) = &session.hls {
+        hls.start(name.clone(), open().await?, hls_config.clone())
+            .await
+            .with_context(|| format!("cannot package static session '{name}'"))?;
+    }
     Ok(())
 }
 
-async fn serve(websocket: WebSocketStream) -> anyhow::Result<()> {
+async fn serve(
+    websocket: WebSocketStream,
+    client: Client,
+    services: Services,
+) -> anyhow::Result<()> {
+    let Services {
+        config,
+        policy,
+        recorder,
+        ptp,
+        alarms,
+        shutdown,
+    } = &services;
     let (payload_tx, mut payload_rx) = mpsc::unbounded_channel();
-    let (stop_tx, _stop_rx) = broadcast::channel(100);
+    let (server_tx, mut server_rx) = mpsc::unbounded_channel();
     let (mut ws_tx, mut ws_rx) = websocket.split();
+    let mut alarm_rx = alarms.subscribe();
+    let mut closing = shutdown.clone();
+    let keepalive = config.keepalive.interval > 0;
+    let ping_interval = Duration::from_secs(config.keepalive.interval.max(1));
+    let mut pings = interval_at(Instant::now() + ping_interval, ping_interval);
 
-    spawn(async move {
-        while let Some(rtp_payload) = payload_rx.recv().await {
-            let msg = Message::Binary(rtp_payload);
+    let mut writer = spawn(async move {
+        loop {
+            let msg = select! {
+                Some(rtp_payload) = payload_rx.recv() => Message::Binary(rtp_payload),
+                Ok(()) = closing.changed() => {
+                    Message::close_with(CloseCode::Away, "server is shutting down")
+                }
+                _ = pings.tick(), if keepalive => Message::Ping(Vec::new()),
+                Ok(event) = alarm_rx.recv() => {
+                    let server_message = match event {
+                        AlarmEvent::Raised(alarm) => ServerMessage::AlarmRaised(alarm),
+                        AlarmEvent::Cleared(alarm) => ServerMessage::AlarmCleared(alarm),
+                    };
+                    match serde_json::to_string(&server_message) {
+                        Ok(json) => Message::Text(json),
+                        Err(e) => {
+                            log::error!("Error serializing server message: {e}");
+                            continue;
+                        }
+                    }
+                }
+                Some(server_message) = server_rx.recv() => {
+                    match serde_json::to_string(&server_message) {
+                        Ok(json) => Message::Text(json),
+                        Err(e) => {
+                            log::error!("Error serializing server message: {e}");
+                            continue;
+                        }
+                    }
+                }
+                else => break,
+            };
+            let close = matches!(msg, Message::Close(_));
             if let Err(e) = ws_tx.send(msg).await {
                 log::error!("Error forwarding rtp payload: {e}");
                 break;
             }
+            if close {
+                break;
+            }
         }
     });
 
-    loop {
-        if let Some(Ok(incoming_msg)) = ws_rx.next().await {
-            if let Message::Text(json) = incoming_msg {
+    let mut transmitter: Option<Transmitter> = None;
+    // the stream started with `Play` has no id
+    let mut playbacks: HashMap<Option<String>, Playback> = HashMap::new();
+    let mut shutdown = shutdown.clone();
+    let idle_timeout = Duration::from_secs(config.keepalive.timeout);
+    let idle = sleep(idle_timeout);
+    tokio::pin!(idle);
+
+    let reason = loop {
+        let incoming = select! {
+            biased;
+            Ok(()) = shutdown.changed() => break DisconnectReason::ShutDown,
+            // sending failed, so the client is gone
+            _ = &mut writer => break DisconnectReason::Failed,
+            _ = &mut idle, if keepalive => break DisconnectReason::TimedOut,
+            incoming = ws_rx.next() => incoming,
+        };
+        let incoming_msg = match incoming {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => {
+                log::info!("Error reading from WS connection: {e}");
+                break DisconnectReason::Failed;
+            }
+            None => break DisconnectReason::Closed,
+        };
+        idle.as_mut().reset(Instant::now() + idle_timeout);
+        match incoming_msg {
+            Message::Text(json) => {
                 if let Ok(client_message) = serde_json::from_str(&json) {
                     match client_message {
-                        ClientMessage::Play(session) => {
-                            if let Some(sd) = match session {
-                                Session::Sdp(sdp) => sdp.parse().ok(),
-                                Session::Custom(sd) => Some(sd),
-                            } {
-                                play(sd, payload_tx.clone(), stop_tx.clone()).await?;
+                        ClientMessage::Play(request) => {
+                            let output = Output {
+                                id: None,
+                                payload_tx: payload_tx.clone(),
+                                server_tx: server_tx.clone(),
+                            };
+                            let mut previous = playbacks.remove(&None);
+                            let started =
+                                play(request, output.clone(), &client, &services, &mut previous);
+                            match started.await {
+                                Ok(p) => {
+                                    playbacks.insert(None, p);
+                                }
+                                Err(e) => {
+                                    // a rejected session leaves the playing one alone
+                                    if let Some(previous) = previous {
+                                        playbacks.insert(None, previous);
+                                    }
+                                    log::error!("Could not play session: {e}");
+                                    output.send_message(ServerMessage::Error(e.to_string()));
+                                }
+                            }
+                        }
+                        ClientMessage::Subscribe { id, request } => {
+                            let output = Output {
+                                id: Some(id.clone()),
+                                payload_tx: payload_tx.clone(),
+                                server_tx: server_tx.clone(),
+                            };
+                            let mut previous = playbacks.remove(&Some(id.clone()));
+                            let started =
+                                play(request, output.clone(), &client, &services, &mut previous);
+                            match started.await {
+                                Ok(p) => {
+                                    playbacks.insert(Some(id), p);
+                                }
+                                Err(e) => {
+                                    if let Some(previous) = previous {
+                                        playbacks.insert(Some(id), previous);
+                                    }
+                                    log::error!("Could not subscribe to session: {e}");
+                                    output.send_message(ServerMessage::Error(e.to_string()));
+                                }
                             }
                         }
                         ClientMessage::Stop => {
-                            stop_tx.send(()).ok();
+                            if let Some(playback) = playbacks.remove(&None) {
+                                playback.stop().await;
+                            }
+                        }
+                        ClientMessage::Unsubscribe { id } => {
+                            if let Some(playback) = playbacks.remove(&Some(id)) {
+                                playback.stop().await;
+                            }
+                        }
+                        ClientMessage::StartRecording { name, request } => {
+                            if let Err(e) = request.session.authorize(&client.permissions, policy) {
+                                log::error!("Could not start recording: {e}");
+                                server_tx.send(ServerMessage::Error(e.to_string())).ok();
+                                continue;
+                            }
+                            match request.session.open(config).await {
+                                Ok(mut stream) => {
+                                    watch(&mut stream, WatchdogConfig::default(), alarms);
+                                    if let Err(e) =
+                                        recorder.start(name, stream, request.config).await
+                                    {
+                                        log::error!("Could not start recording: {e}");
+                                        server_tx.send(ServerMessage::Error(e.to_string())).ok();
+                                    }
+                                }
+                                Err(e) => {
+                                    log::error!("Could not start recording: {e}");
+                                    server_tx.send(ServerMessage::Error(e.to_string())).ok();
+                                }
+                            }
+                        }
+                        ClientMessage::StopRecording(name) => {
+                            let Some(descriptor) = recorder.descriptor(&name) else {
+                                continue;
+                            };
+                            let known = policy.known_sessions();
+                            if let Err(e) = client.permissions.check(&descriptor, &known) {
+                                log::error!("Could not stop recording: {e}");
+                                server_tx.send(ServerMessage::Error(e.to_string())).ok();
+                                continue;
+                            }
+                            recorder.stop(&name);
+                        }
+                        ClientMessage::Transmit(sd) => {
+                            transmitter.take();
+                            let allowed = client
+                                .permissions
+                                .check(&sd, &policy.known_sessions())
+                                .and_then(|_| policy.check_transmit(&sd));
+                            if let Err(e) = allowed {
+                                log::error!("Could not start transmitter: {e}");
+                                server_tx.send(ServerMessage::Error(e.to_string())).ok();
+                                continue;
+                            }
+                            match Transmitter::start(
+                                sd,
+                                config.interfaces.transmit,
+                                config.buffers.transmit_buffer_seconds,
+                            )
+                            .await
+                            {
+                                Ok(t) => {
+                                    server_tx
+                                        .send(ServerMessage::Transmitting { sdp: t.sdp.clone() })
+                                        .ok();
+                                    transmitter = Some(t);
+                                }
+                                Err(e) => {
+                                    log::error!("Could not start transmitter: {e}");
+                                    server_tx.send(ServerMessage::Error(e.to_string())).ok();
+                                }
+                            }
+                        }
+                        ClientMessage::StopTransmit => {
+                            transmitter.take();
+                        }
+                        ClientMessage::ResetLoudness { id } => {
+                            if let Some(playback) = playbacks.get(&id) {
+                                playback.control.send(PlaybackControl::ResetLoudness).ok();
+                            }
+                        }
+                        ClientMessage::GetPtpStatus => {
+                            server_tx.send(ServerMessage::PtpStatus(ptp.status())).ok();
+                        }
+                        ClientMessage::GetStats { id } => {
+                            let message = match (playbacks.get(&id), &id) {
+                                (Some(playback), _) => ServerMessage::Stats(
+                                    playback.stats.lock().expect("mutex poisoned").clone(),
+                                ),
+                                (None, None) => ServerMessage::Error("not playing".to_owned()),
+                                (None, Some(_)) => {
+                                    ServerMessage::Error("no such subscription".to_owned())
+                                }
+                            };
+                            let message = match id {
+                                Some(id) => ServerMessage::Subscription {
+                                    id,
+                                    message: Box::new(message),
+                                },
+                                None => message,
+                            };
+                            server_tx.send(message).ok();
                         }
                     }
                 }
             }
-        } else {
-            stop_tx.send(()).ok();
-            log::info!("Client disconnected.");
-            break;
+            Message::Binary(pcm) => {
+                if let Some(t) = &transmitter {
+                    t.send(pcm).ok();
+                }
+            }
+            _ => {}
         }
-    }
+    };
+    log::info!("Client disconnected: {reason}");
 
+    for (_, playback) in playbacks.drain() {
+        playback.stop().await;
+    }
+    transmitter.take();
+    if reason == DisconnectReason::ShutDown {
+        // let the close frame go out before the connection is dropped
+        writer.await.ok();
+    } else {
+        // a dead client may never accept the frames the writer is blocked on
+        writer.abort();
+    }
+    client.disconnect(reason);
     Ok(())
 }
 
 async fn play(
-    sd: SessionDescriptor,
-    payload_tx: UnboundedSender<Vec<u8>>,
-    stop_tx: broadcast::Sender<()>,
-) -> anyhow::Result<()> {
-    stop_tx.send(()).ok();
-    sleep(Duration::from_millis(100)).await;
-    log::info!("Playing {sd:?}");
-    let mut stream = Stream::new(sd, Ipv4Addr::UNSPECIFIED).await?;
-    stream.play(payload_tx, stop_tx).await?;
+    request: PlayRequest,
+    output: Output,
+    client: &Client,
+    services: &Services,
+    replaced: &mut Option<Playback>,
+) -> anyhow::Result<Playback> {
+    let Services {
+        config,
+        policy,
+        ptp,
+        alarms,
+        ..
+    } = services;
+    let ptp = ptp.clone();
+    if let Some(id) = &output.id {
+        if id.is_empty() || id.len() > u8::MAX as usize {
+            return Err(anyhow!("subscription ids must be 1 to 255 bytes long"));
+        }
+    }
+    request.session.authorize(&client.permissions, policy)?;
+    let watchdog = request.watchdog.clone().unwrap_or_default();
+    watchdog.validate()?;
+    let replaced_slot = replaced.as_ref().map(|p| &p.slot);
+    let slot = client.replace_stream(replaced_slot, request.bitrate()?)?;
+    log::info!("Playing {:?}", request.session);
+    let mut stream = request.session.open(config).await?;
+    watch(&mut stream, watchdog, alarms);
+    stream.receiver_reports = config.rtcp_receiver_reports;
+    let mut events = stream.events.subscribe();
+    let descriptor = stream.descriptor.clone();
+    let mut resampler = match request.target_sample_rate {
+        Some(rate) if rate != descriptor.sample_rate || request.drift_compensation => Some(
+            Resampler::new(descriptor.channels, descriptor.sample_rate, rate)?,
+        ),
+        _ => None,
+    };
+    let mut meter = request
+        .meter
+        .as_ref()
+        .map(|config| Meter::new(descriptor.channels, descriptor.sample_rate, config))
+        .transpose()?;
+    let mut loudness = request
+        .loudness
+        .as_ref()
+        .map(|config| LoudnessMeter::new(descriptor.channels, descriptor.sample_rate, config))
+        .transpose()?;
+    let mut channel_status = match (request.channel_status, &descriptor.bit_depth) {
+        (false, _) => None,
+        (true, BitDepth::AM824) => Some(ChannelStatusDecoder::new(descriptor.channels)),
+        (true, bit_depth) => {
+            return Err(anyhow!("{bit_depth} sessions carry no channel status"));
+        }
+    };
+    let stats = stream.stats.clone();
+    let (control, mut control_rx) = mpsc::unbounded_channel();
+    let (packet_tx, mut packet_rx) = mpsc::unbounded_channel();
+    let stream_handle = stream.play(packet_tx).await?;
     log::info!("Stream started.");
-    Ok(())
+    // nothing can fail from here on, so the replaced playback is only taken now
+    let mut previous = replaced.take();
+
+    let session = session_label(&descriptor);
+    let watch = descriptor
+        .reference_clock
+        .clone()
+        .map(|clock| (ptp.watch(session.clone(), clock.clone()), clock));
+    let events_output = output.clone();
+
+    let forwarder = spawn(async move {
+        let mut clock_checks = interval(CLOCK_CHECK_INTERVAL);
+        let mut clock_status = None;
+        // the replaced stream keeps playing until this one delivers, so switching is gapless
+        let switch_timeout = sleep(SWITCH_TIMEOUT);
+        tokio::pin!(switch_timeout);
+        'forward: loop {
+            select! {
+                packet = packet_rx.recv() => {
+                    if let Some(previous) = previous.take() {
+                        previous.stop().await;
+                    }
+                    let Some(Packet { mut payload, media_time, .. }) = packet else {
+                        break;
+                    };
+                    if let Some(decoder) = &mut channel_status {
+                        for status in decoder.process(&payload) {
+                            output.send_message(ServerMessage::ChannelStatus(status));
+                        }
+                    }
+                    let samples = if meter.is_some() || loudness.is_some() || resampler.is_some() {
+                        pcm::decode(&payload, &descriptor.bit_depth)
+                    } else {
+                        Vec::new()
+                    };
+                    if let Some(loudness) = &mut loudness {
+                        for frame in loudness.process(&samples) {
+                            output.send_message(ServerMessage::Loudness(frame));
+                        }
+                    }
+                    if let (Some(meter), Some(config)) = (&mut meter, &request.meter) {
+                        for frame in meter.process(&samples) {
+                            let sent = match config.format {
+                                MeterFormat::Json => output.send_message(ServerMessage::Meter(frame)),
+                                MeterFormat::Binary => output.send_payload(frame.to_bytes()),
+                            };
+                            if !sent {
+                                break 'forward;
+                            }
+                        }
+                        continue;
+                    }
+                    if let Some(resampler) = &mut resampler {
+                        if request.drift_compensation {
+                            let drift = stats.lock().expect("mutex poisoned").drift_ppm;
+                            resampler.set_drift(drift.unwrap_or(0.0));
+                        }
+                        payload = pcm::encode(&resampler.process(&samples), &descriptor.bit_depth.pcm());
+                    } else {
+                        payload = pcm::convert(&payload, &descriptor.bit_depth, &descriptor.bit_depth.pcm());
+                    }
+                    if request.media_time {
+                        let media_time = media_time.unwrap_or(u64::MAX).to_be_bytes();
+                        payload.splice(0..0, media_time);
+                    }
+                    if !output.send_payload(payload) {
+                        break;
+                    }
+                }
+                Some(control) = control_rx.recv() => match control {
+                    PlaybackControl::ResetLoudness => {
+                        if let Some(loudness) = &mut loudness {
+                            loudness.reset();
+                        }
+                    }
+                },
+                _ = &mut switch_timeout, if previous.is_some() => {
+                    if let Some(previous) = previous.take() {
+                        previous.stop().await;
+                    }
+                }
+                _ = clock_checks.tick(), if watch.is_some() => {
+                    if let Some((_, reference_clock)) = &watch {
+                        let status = ptp.clock_status(reference_clock);
+                        if clock_status.as_ref() != Some(&status) {
+                            clock_status = Some(status.clone());
+                            output.send_message(ServerMessage::ClockStatus(SessionClockStatus {
+                                session: session.clone(),
+                                reference_clock: reference_clock.clone(),
+                                status,
+                            }));
+                        }
+                    }
+                }
+            }
+        }
+        if let Some((id, _)) = watch {
+            ptp.unwatch(id);
+        }
+    });
+
+    spawn(async move {
+        while let Ok(event) = events.recv().await {
+            let message = match event {
+                StreamEvent::Goodbye { reason } => ServerMessage::StreamEnded { reason },
+                StreamEvent::PayloadSizeMismatch(error) => {
+                    ServerMessage::PayloadSizeMismatch(error)
+                }
+            };
+            if !events_output.send_message(message) {
+                break;
+            }
+        }
+    });
+
+    Ok(Playback {
+        stats: stream.stats.clone(),
+        control,
+        stream: stream_handle,
+        forwarder,
+        slot,
+    })
+}
+
+fn session_label(descriptor: &SessionDescriptor) -> String {
+    descriptor.session_name.clone().unwrap_or_else(|| {
+        format!(
+            "{}:{}",
+            descriptor.multicast_address, descriptor.multicast_port
+        )
+    })
+}
+
+fn watch(stream: &mut Stream, config: WatchdogConfig, alarms: &AlarmRegistry) {
+    stream.watchdog = Some(Watchdog::new(
+        session_label(&stream.descriptor),
+        stream.descriptor.clone(),
+        config,
+        alarms.clone(),
+    ));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        auth::{sign_url, AuthConfig, TokenConfig},
+        config::LimitConfig,
+        generator::Signal,
+    };
+    use poem::http::Method;
+
+    #[test]
+    fn reject_invalid_descriptors() {
+        let valid = SessionDescriptor::default();
+        assert!(Session::Custom(valid.clone()).descriptor().is_ok());
+        for descriptor in [
+            SessionDescriptor {
+                channels: 0,
+                ..valid.clone()
+            },
+            SessionDescriptor {
+                sample_rate: 0,
+                ..valid.clone()
+            },
+            SessionDescriptor {
+                packet_time: 0.0,
+                ..valid.clone()
+            },
+        ] {
+            assert!(Session::Custom(descriptor).descriptor().is_err());
+        }
+    }
+
+    #[test]
+    fn rejected_switch_keeps_playing() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let clients = ClientRegistry::new(&LimitConfig {
+                max_streams_per_client: Some(1),
+                ..Default::default()
+            });
+            let client = clients.connect(None, Permissions::all()).unwrap();
+            let services = Services {
+                config: Arc::new(Config::default()),
+                policy: MulticastPolicy::new(Default::default(), Default::default()),
+                recorder: RecorderRegistry::default(),
+                ptp: PtpMonitor::default(),
+                alarms: AlarmRegistry::default(),
+                shutdown: watch::channel(false).1,
+            };
+            let (payload_tx, mut payload_rx) = mpsc::unbounded_channel();
+            let (server_tx, _server_rx) = mpsc::unbounded_channel();
+            let output = Output {
+                id: None,
+                payload_tx,
+                server_tx,
+            };
+            let valid = SessionDescriptor::default();
+            let request = |session: Session| -> PlayRequest {
+                serde_json::from_value(serde_json::to_value(session).unwrap()).unwrap()
+            };
+            let generator = request(Session::Generator(GeneratorSession {
+                signal: Signal::PinkNoise,
+                level: -18.0,
+                descriptor: valid.clone(),
+            }));
+            let mut playing = None;
+            let playback = play(generator, output.clone(), &client, &services, &mut playing)
+                .await
+                .unwrap();
+            payload_rx.recv().await.unwrap();
+
+            let mut playing = Some(playback);
+            // fails to open after the stream has been admitted in place of the playing one
+            let rejected = request(Session::Capture(CaptureSession {
+                file: "missing.pcap".into(),
+                session: Box::new(Session::Custom(valid)),
+                speed: Default::default(),
+            }));
+            assert!(play(rejected, output, &client, &services, &mut playing)
+                .await
+                .is_err());
+            assert!(playing.is_some());
+            while payload_rx.try_recv().is_ok() {}
+            assert!(timeout(Duration::from_secs(1), payload_rx.recv())
+                .await
+                .unwrap()
+                .is_some());
+        });
+    }
+
+    #[test]
+    fn hls_segments_with_auth() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let hls = HlsRegistry::default();
+            let stream = Stream::from_generator(GeneratorSession {
+                signal: Signal::PinkNoise,
+                level: -18.0,
+                descriptor: SessionDescriptor::default(),
+            })
+            .unwrap();
+            let config = HlsConfig {
+                segment_duration: 0.05,
+                window: 6,
+            };
+            hls.start("stage".to_owned(), stream, config).await.unwrap();
+            let authenticator = Authenticator::new(Some(AuthConfig {
+                url_secret: Some("url key".to_owned()),
+                ..Default::default()
+            }));
+            let app = Route::new()
+                .at("/hls/:name/:file", get(hls_file))
+                .around(move |endpoint, request| {
+                    authenticate(endpoint, request, authenticator.clone())
+                })
+                .data(MulticastPolicy::new(Default::default(), Default::default()))
+                .data(hls.clone());
+            let get = |uri: String| app.get_response(Request::builder().uri_str(&uri).finish());
+
+            let playlist_url = sign_url(
+                "url key",
+                "/hls/stage/playlist.m3u8",
+                "expires=4000000000&sessions=*",
+            );
+            let segment = loop {
+                let mut response = get(playlist_url.clone()).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                let playlist = response.take_body().into_string().await.unwrap();
+                if let Some(segment) = playlist.lines().find(|l| l.starts_with("segment")) {
+                    break segment.to_owned();
+                }
+                sleep(Duration::from_millis(20)).await;
+            };
+            let response = get(format!("/hls/stage/{segment}")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let (unsigned, _) = segment.split_once('?').unwrap();
+            let response = get(format!("/hls/stage/{unsigned}")).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let response = get(playlist_url.replace("/stage/", "/studio/")).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            hls.stop_all().await;
+        });
+    }
+
+    #[test]
+    fn restricted_tokens_only_reach_their_sessions() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let hls = HlsRegistry::default();
+            let descriptor = |name: &str, group: [u8; 4]| SessionDescriptor {
+                session_name: Some(name.to_owned()),
+                multicast_address: group.into(),
+                ..Default::default()
+            };
+            for (name, group) in [("stage", [239, 69, 3, 4]), ("studio", [239, 69, 3, 5])] {
+                let stream = Stream::from_generator(GeneratorSession {
+                    signal: Signal::PinkNoise,
+                    level: -18.0,
+                    descriptor: descriptor(name, group),
+                })
+                .unwrap();
+                hls.start(name.to_owned(), stream, HlsConfig::default())
+                    .await
+                    .unwrap();
+            }
+            let policy = MulticastPolicy::new(Default::default(), Default::default())
+                .with_sessions(vec![descriptor("Stage", [239, 69, 3, 4])]);
+            let authenticator = Authenticator::new(Some(AuthConfig {
+                tokens: vec![TokenConfig {
+                    token: "stage".to_owned(),
+                    permissions: Permissions {
+                        sessions: vec!["Stage".to_owned()],
+                        groups: Vec::new(),
+                    },
+                }],
+                ..Default::default()
+            }));
+            let app = Route::new()
+                .at("/hls/:name", poem::delete(stop_hls))
+                .at("/hls/:name/:file", get(hls_file))
+                .around(move |endpoint, request| {
+                    authenticate(endpoint, request, authenticator.clone())
+                })
+                .data(policy)
+                .data(hls.clone());
+            let send = |method: Method, uri: &str| {
+                let request = Request::builder()
+                    .method(method)
+                    .uri_str(uri)
+                    .header("authorization", "Bearer stage")
+                    .finish();
+                app.get_response(request)
+            };
+
+            let response = send(Method::GET, "/hls/stage/init.mp4").await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let response = send(Method::GET, "/hls/studio/segment0.m4s").await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            let response = send(Method::DELETE, "/hls/studio").await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            assert!(hls.descriptor("studio").is_some());
+            let response = send(Method::DELETE, "/hls/stage").await;
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            hls.stop_all().await;
+        });
+    }
+
+    #[test]
+    fn parse_client_messages() {
+        let parse = |json: &str| serde_json::from_str::<ClientMessage>(json).unwrap();
+        assert_eq!(
+            parse(r#"{"unsubscribe":{"id":"a"}}"#),
+            ClientMessage::Unsubscribe { id: "a".to_owned() }
+        );
+        assert_eq!(
+            parse(r#"{"getStats":{"id":"a"}}"#),
+            ClientMessage::GetStats {
+                id: Some("a".to_owned())
+            }
+        );
+        assert_eq!(
+            parse(r#"{"getStats":{}}"#),
+            ClientMessage::GetStats { id: None }
+        );
+        assert_eq!(
+            parse(r#"{"resetLoudness":{}}"#),
+            ClientMessage::ResetLoudness { id: None }
+        );
+        assert_eq!(parse(r#""stop""#), ClientMessage::Stop);
+    }
 }
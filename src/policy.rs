@@ -0,0 +1,293 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::Ipv4Addr, str::FromStr, sync::Arc};
+
+use crate::{sap::SessionDirectory, SessionDescriptor};
+
+/// The local network control block, used by routing protocols, mDNS and the like.
+const LOCAL_NETWORK_CONTROL: Ipv4Cidr = Ipv4Cidr {
+    address: Ipv4Addr::new(224, 0, 0, 0),
+    prefix: 24,
+};
+
+/// Multicast groups and ports clients may make the server receive from or transmit to.
+/// Sessions configured by the operator are not restricted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    /// Permitted multicast groups, like `239.69.0.0/16`. Groups in 224.0.0.0/24 are never
+    /// permitted.
+    pub groups: Vec<Ipv4Cidr>,
+    /// Permitted UDP ports, like `5004` or `5004-5100`.
+    pub ports: Vec<PortRange>,
+    /// Only receive sessions currently announced via SAP.
+    pub announced_only: bool,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        PolicyConfig {
+            groups: vec![Ipv4Cidr {
+                address: Ipv4Addr::new(224, 0, 0, 0),
+                prefix: 4,
+            }],
+            ports: vec![PortRange {
+                start: 1024,
+                end: u16::MAX,
+            }],
+            announced_only: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Ipv4Cidr {
+    pub address: Ipv4Addr,
+    pub prefix: u8,
+}
+
+impl Ipv4Cidr {
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+        u32::from(address) & mask == u32::from(self.address) & mask
+    }
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = s.split_once('/').unwrap_or((s, "32"));
+        let address = address
+            .parse()
+            .map_err(|_| anyhow!("invalid address in {s}"))?;
+        let prefix = prefix
+            .parse()
+            .ok()
+            .filter(|&p| p <= 32)
+            .ok_or_else(|| anyhow!("invalid prefix length in {s}"))?;
+        Ok(Ipv4Cidr { address, prefix })
+    }
+}
+
+impl TryFrom<String> for Ipv4Cidr {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Ipv4Cidr> for String {
+    fn from(cidr: Ipv4Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let port = |p: &str| {
+            p.trim()
+                .parse::<u16>()
+                .map_err(|_| anyhow!("invalid port range {s}"))
+        };
+        let (start, end) = (port(start)?, port(end)?);
+        if start > end {
+            return Err(anyhow!("invalid port range {s}"));
+        }
+        Ok(PortRange { start, end })
+    }
+}
+
+impl TryFrom<String> for PortRange {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PortRange> for String {
+    fn from(range: PortRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// Checks the multicast sessions requested by clients against the configured policy.
+#[derive(Clone)]
+pub struct MulticastPolicy {
+    config: Arc<PolicyConfig>,
+    directory: SessionDirectory,
+    configured: Arc<Vec<SessionDescriptor>>,
+}
+
+impl MulticastPolicy {
+    pub fn new(config: PolicyConfig, directory: SessionDirectory) -> Self {
+        MulticastPolicy {
+            config: Arc::new(config),
+            directory,
+            configured: Default::default(),
+        }
+    }
+
+    /// Sets the sessions of the configuration, named as configured.
+    pub fn with_sessions(mut self, sessions: Vec<SessionDescriptor>) -> Self {
+        self.configured = Arc::new(sessions);
+        self
+    }
+
+    /// The configured and the currently announced sessions, whose names can be trusted unlike
+    /// those of sessions described by clients.
+    pub fn known_sessions(&self) -> Vec<SessionDescriptor> {
+        let mut sessions = self.configured.as_ref().clone();
+        sessions.extend(self.directory.sessions());
+        sessions
+    }
+
+    /// Fails unless the session may be joined.
+    pub fn check_receive(&self, descriptor: &SessionDescriptor) -> anyhow::Result<()> {
+        self.check(descriptor)?;
+        let (group, port) = (descriptor.multicast_address, descriptor.multicast_port);
+        if self.config.announced_only && !self.directory.announces(group, port) {
+            return Err(anyhow!("no session is announced on {group}:{port}"));
+        }
+        Ok(())
+    }
+
+    /// Fails unless the session may be transmitted. Transmitted sessions need not be announced,
+    /// the transmitter announces them itself.
+    pub fn check_transmit(&self, descriptor: &SessionDescriptor) -> anyhow::Result<()> {
+        self.check(descriptor)
+    }
+
+    fn check(&self, descriptor: &SessionDescriptor) -> anyhow::Result<()> {
+        let (group, port) = (descriptor.multicast_address, descriptor.multicast_port);
+        if !group.is_multicast() || LOCAL_NETWORK_CONTROL.contains(group) {
+            return Err(anyhow!("{group} is not a permitted multicast group"));
+        }
+        if !self.config.groups.iter().any(|g| g.contains(group)) {
+            return Err(anyhow!("multicast group {group} is not permitted"));
+        }
+        if !self.config.ports.iter().any(|p| p.contains(port)) {
+            return Err(anyhow!("port {port} is not permitted"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn descriptor(group: [u8; 4], port: u16) -> SessionDescriptor {
+        let mut descriptor: SessionDescriptor = "v=0\r\n\
+            o=- 1 1 IN IP4 10.0.0.1\r\n\
+            s=Stage\r\n\
+            c=IN IP4 239.69.3.4/32\r\n\
+            t=0 0\r\n\
+            m=audio 5004 RTP/AVP 97\r\n\
+            a=rtpmap:97 L24/48000/2\r\n\
+            a=ptime:1\r\n"
+            .parse()
+            .unwrap();
+        descriptor.multicast_address = group.into();
+        descriptor.multicast_port = port;
+        descriptor
+    }
+
+    #[test]
+    fn parse_ranges() {
+        let cidr: Ipv4Cidr = "239.69.0.0/16".parse().unwrap();
+        assert!(cidr.contains(Ipv4Addr::new(239, 69, 200, 1)));
+        assert!(!cidr.contains(Ipv4Addr::new(239, 70, 0, 1)));
+        assert_eq!(cidr.to_string(), "239.69.0.0/16");
+        assert!("0.0.0.0/0"
+            .parse::<Ipv4Cidr>()
+            .unwrap()
+            .contains(Ipv4Addr::BROADCAST));
+        assert_eq!("239.1.2.3".parse::<Ipv4Cidr>().unwrap().prefix, 32);
+        assert!("239.1.2.3/33".parse::<Ipv4Cidr>().is_err());
+
+        assert_eq!(
+            "5004-5100".parse::<PortRange>().unwrap(),
+            PortRange {
+                start: 5004,
+                end: 5100
+            }
+        );
+        assert!("5004".parse::<PortRange>().unwrap().contains(5004));
+        assert!("5100-5004".parse::<PortRange>().is_err());
+    }
+
+    #[test]
+    fn check_sessions() {
+        let policy = MulticastPolicy::new(Default::default(), Default::default());
+        assert!(policy
+            .check_receive(&descriptor([239, 69, 3, 4], 5004))
+            .is_ok());
+        assert!(policy
+            .check_receive(&descriptor([224, 0, 0, 251], 5353))
+            .is_err());
+        assert!(policy
+            .check_receive(&descriptor([239, 69, 3, 4], 22))
+            .is_err());
+        assert!(policy
+            .check_receive(&descriptor([10, 0, 0, 1], 5004))
+            .is_err());
+
+        let policy = MulticastPolicy::new(
+            PolicyConfig {
+                groups: vec!["239.69.0.0/16".parse().unwrap()],
+                ports: vec!["5004".parse().unwrap()],
+                announced_only: true,
+            },
+            Default::default(),
+        );
+        let error = policy
+            .check_receive(&descriptor([239, 69, 3, 4], 5004))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "no session is announced on 239.69.3.4:5004"
+        );
+        assert!(policy
+            .check_transmit(&descriptor([239, 69, 3, 4], 5004))
+            .is_ok());
+        assert!(policy
+            .check_transmit(&descriptor([239, 70, 3, 4], 5004))
+            .is_err());
+    }
+}
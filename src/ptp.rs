@@ -0,0 +1,442 @@
+use crate::ReferenceClock;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Socket, Type};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{net::UdpSocket, spawn, time::Instant};
+
+pub const PTP_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 1, 129);
+pub const PTP_EVENT_PORT: u16 = 319;
+pub const PTP_GENERAL_PORT: u16 = 320;
+/// TAI - UTC in seconds since 2017, assumed while no grandmaster announces it.
+pub const DEFAULT_UTC_OFFSET: i16 = 37;
+/// A grandmaster is considered gone if no announce was received for this long.
+const ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const HEADER_LEN: usize = 34;
+const MSG_SYNC: u8 = 0x0;
+const MSG_FOLLOW_UP: u8 = 0x8;
+const MSG_ANNOUNCE: u8 = 0xB;
+const FLAG_TWO_STEP: u8 = 0x02;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Announce {
+    /// Clock identity of the port sending announce messages.
+    pub master: String,
+    pub grandmaster: String,
+    pub priority1: u8,
+    pub priority2: u8,
+    pub clock_class: u8,
+    pub clock_accuracy: u8,
+    pub clock_variance: u16,
+    pub steps_removed: u16,
+    /// TAI - UTC in seconds.
+    pub utc_offset: i16,
+    pub time_source: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PtpMessage {
+    Announce(Announce),
+    Sync {
+        sequence_id: u16,
+        two_step: bool,
+        /// Origin time in nanoseconds since the PTP epoch.
+        origin_time: u64,
+    },
+    FollowUp {
+        sequence_id: u16,
+        precise_origin_time: u64,
+    },
+    Other(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DomainStatus {
+    pub domain: u8,
+    pub announce: Option<Announce>,
+    pub last_announce_ms: Option<u64>,
+    pub last_sync_ms: Option<u64>,
+    pub sync_messages: u64,
+    /// Offset of the master's clock from the local system clock in nanoseconds, ignoring path
+    /// delay. Close to zero if the local clock is disciplined by this domain.
+    pub master_offset_ns: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ClockStatus {
+    /// The domain's current grandmaster matches the session's reference clock.
+    Locked,
+    /// The domain is governed by a different grandmaster than the session announces.
+    Mismatch { grandmaster: String },
+    /// No grandmaster is currently announced in the session's domain.
+    NoGrandmaster,
+    /// The session does not reference a PTP clock.
+    NotPtp,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionClockStatus {
+    pub session: String,
+    pub reference_clock: ReferenceClock,
+    pub status: ClockStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PtpStatus {
+    pub domains: Vec<DomainStatus>,
+    pub sessions: Vec<SessionClockStatus>,
+}
+
+#[derive(Default)]
+struct DomainState {
+    announce: Option<(Announce, Instant)>,
+    last_sync: Option<Instant>,
+    sync_messages: u64,
+    pending_sync: Option<(u16, SystemTime)>,
+    master_offset_ns: Option<i64>,
+}
+
+/// Passively tracks the PTP grandmasters announced on the network, and the reference clocks of
+/// the sessions being played.
+#[derive(Clone, Default)]
+pub struct PtpMonitor {
+    domains: Arc<Mutex<BTreeMap<u8, DomainState>>>,
+    sessions: Arc<Mutex<HashMap<u64, (String, ReferenceClock)>>>,
+    next_session_id: Arc<AtomicU64>,
+}
+
+impl PtpMonitor {
+    /// Starts listening for PTP messages. Failing to open the PTP ports (which usually requires
+    /// elevated privileges) is not fatal, the monitor will just never see a grandmaster.
+    pub fn start(local_address: Ipv4Addr) -> Self {
+        let monitor = PtpMonitor::default();
+        for port in [PTP_EVENT_PORT, PTP_GENERAL_PORT] {
+            match open_socket(local_address, port) {
+                Ok(socket) => {
+                    spawn(monitor.clone().listen(socket));
+                }
+                Err(e) => log::warn!("Could not listen for PTP on port {port}: {e}"),
+            }
+        }
+        monitor
+    }
+
+    pub fn domains(&self) -> Vec<DomainStatus> {
+        let domains = self.domains.lock().expect("mutex poisoned");
+        domains
+            .iter()
+            .map(|(domain, state)| DomainStatus {
+                domain: *domain,
+                announce: state.announce.as_ref().map(|(a, _)| a.clone()),
+                last_announce_ms: state
+                    .announce
+                    .as_ref()
+                    .map(|(_, at)| at.elapsed().as_millis() as u64),
+                last_sync_ms: state.last_sync.map(|at| at.elapsed().as_millis() as u64),
+                sync_messages: state.sync_messages,
+                master_offset_ns: state.master_offset_ns,
+            })
+            .collect()
+    }
+
+    /// TAI - UTC in seconds as announced by the grandmaster of the domain.
+    pub fn utc_offset(&self, domain: u8) -> Option<i16> {
+        let domains = self.domains.lock().expect("mutex poisoned");
+        domains
+            .get(&domain)?
+            .announce
+            .as_ref()
+            .filter(|(_, at)| at.elapsed() < ANNOUNCE_TIMEOUT)
+            .map(|(announce, _)| announce.utc_offset)
+    }
+
+    pub fn clock_status(&self, reference_clock: &ReferenceClock) -> ClockStatus {
+        clock_status(reference_clock, &self.domains())
+    }
+
+    pub fn status(&self) -> PtpStatus {
+        let domains = self.domains();
+        let sessions = self.sessions.lock().expect("mutex poisoned");
+        let mut sessions: Vec<SessionClockStatus> = sessions
+            .values()
+            .map(|(session, reference_clock)| SessionClockStatus {
+                session: session.clone(),
+                reference_clock: reference_clock.clone(),
+                status: clock_status(reference_clock, &domains),
+            })
+            .collect();
+        sessions.sort_by(|a, b| a.session.cmp(&b.session));
+        PtpStatus { domains, sessions }
+    }
+
+    /// Registers the reference clock of a played session, to be included in the status until
+    /// unwatched.
+    pub fn watch(&self, session: String, reference_clock: ReferenceClock) -> u64 {
+        let id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+        self.sessions
+            .lock()
+            .expect("mutex poisoned")
+            .insert(id, (session, reference_clock));
+        id
+    }
+
+    pub fn unwatch(&self, id: u64) {
+        self.sessions.lock().expect("mutex poisoned").remove(&id);
+    }
+
+    async fn listen(self, socket: UdpSocket) {
+        let mut buf = [0; 1500];
+        loop {
+            let len = match socket.recv(&mut buf).await {
+                Ok(len) => len,
+                Err(e) => {
+                    log::error!("Error receiving PTP message: {e}");
+                    break;
+                }
+            };
+            let received = SystemTime::now();
+            match parse(&buf[..len]) {
+                Ok((domain, message)) => self.update(domain, message, received),
+                Err(e) => log::debug!("Ignoring PTP message: {e}"),
+            }
+        }
+    }
+
+    fn update(&self, domain: u8, message: PtpMessage, received: SystemTime) {
+        let mut domains = self.domains.lock().expect("mutex poisoned");
+        let state = domains.entry(domain).or_default();
+        match message {
+            PtpMessage::Announce(announce) => {
+                let changed = state
+                    .announce
+                    .as_ref()
+                    .is_none_or(|(a, _)| a.grandmaster != announce.grandmaster);
+                if changed {
+                    log::info!(
+                        "PTP domain {domain} is governed by grandmaster {}",
+                        announce.grandmaster
+                    );
+                }
+                state.announce = Some((announce, Instant::now()));
+            }
+            PtpMessage::Sync {
+                sequence_id,
+                two_step,
+                origin_time,
+            } => {
+                state.last_sync = Some(Instant::now());
+                state.sync_messages += 1;
+                if two_step {
+                    state.pending_sync = Some((sequence_id, received));
+                } else {
+                    state.master_offset_ns = master_offset(origin_time, received, state);
+                }
+            }
+            PtpMessage::FollowUp {
+                sequence_id,
+                precise_origin_time,
+            } => {
+                if let Some((_, received)) = state
+                    .pending_sync
+                    .take_if(|(pending, _)| *pending == sequence_id)
+                {
+                    state.master_offset_ns = master_offset(precise_origin_time, received, state);
+                }
+            }
+            PtpMessage::Other(_) => {}
+        }
+    }
+}
+
+fn master_offset(origin_time: u64, received: SystemTime, state: &DomainState) -> Option<i64> {
+    // PTP time is TAI, the system clock UTC
+    let utc_offset = state.announce.as_ref()?.0.utc_offset as i64 * 1_000_000_000;
+    let received = received.duration_since(UNIX_EPOCH).ok()?.as_nanos() as i64;
+    Some(origin_time as i64 - utc_offset - received)
+}
+
+fn clock_status(reference_clock: &ReferenceClock, domains: &[DomainStatus]) -> ClockStatus {
+    let ReferenceClock::Ptp {
+        grandmaster,
+        domain,
+        ..
+    } = reference_clock
+    else {
+        return ClockStatus::NotPtp;
+    };
+    let domain = domain.unwrap_or(0);
+    let current = domains
+        .iter()
+        .find(|d| d.domain == domain)
+        .filter(|d| {
+            d.last_announce_ms
+                .is_some_and(|ms| ms < ANNOUNCE_TIMEOUT.as_millis() as u64)
+        })
+        .and_then(|d| d.announce.as_ref());
+
+    match (current, grandmaster) {
+        (None, _) => ClockStatus::NoGrandmaster,
+        (Some(current), Some(expected)) if !current.grandmaster.eq_ignore_ascii_case(expected) => {
+            ClockStatus::Mismatch {
+                grandmaster: current.grandmaster.clone(),
+            }
+        }
+        _ => ClockStatus::Locked,
+    }
+}
+
+fn open_socket(local_address: Ipv4Addr, port: u16) -> anyhow::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
+    socket.set_reuse_address(true)?;
+    socket.join_multicast_v4(&PTP_ADDRESS, &local_address)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Parses a PTPv2 message, returning its domain number and content.
+pub fn parse(data: &[u8]) -> anyhow::Result<(u8, PtpMessage)> {
+    if data.len() < HEADER_LEN {
+        return Err(anyhow!("truncated PTP message"));
+    }
+    let version = data[1] & 0x0F;
+    if version != 2 {
+        return Err(anyhow!("unsupported PTP version: {version}"));
+    }
+    let message_type = data[0] & 0x0F;
+    let domain = data[4];
+    let two_step = data[6] & FLAG_TWO_STEP != 0;
+    let source = clock_identity(&data[20..28]);
+    let sequence_id = u16::from_be_bytes([data[30], data[31]]);
+    let body = &data[HEADER_LEN..];
+
+    let message = match message_type {
+        MSG_ANNOUNCE => {
+            if body.len() < 30 {
+                return Err(anyhow!("truncated PTP announce message"));
+            }
+            PtpMessage::Announce(Announce {
+                master: source,
+                utc_offset: i16::from_be_bytes([body[10], body[11]]),
+                priority1: body[13],
+                clock_class: body[14],
+                clock_accuracy: body[15],
+                clock_variance: u16::from_be_bytes([body[16], body[17]]),
+                priority2: body[18],
+                grandmaster: clock_identity(&body[19..27]),
+                steps_removed: u16::from_be_bytes([body[27], body[28]]),
+                time_source: body[29],
+            })
+        }
+        MSG_SYNC => PtpMessage::Sync {
+            sequence_id,
+            two_step,
+            origin_time: timestamp(body)?,
+        },
+        MSG_FOLLOW_UP => PtpMessage::FollowUp {
+            sequence_id,
+            precise_origin_time: timestamp(body)?,
+        },
+        other => PtpMessage::Other(other),
+    };
+
+    Ok((domain, message))
+}
+
+fn timestamp(body: &[u8]) -> anyhow::Result<u64> {
+    let ts = body
+        .get(..10)
+        .ok_or_else(|| anyhow!("truncated PTP timestamp"))?;
+    let seconds = u64::from_be_bytes([0, 0, ts[0], ts[1], ts[2], ts[3], ts[4], ts[5]]);
+    let nanoseconds = u32::from_be_bytes([ts[6], ts[7], ts[8], ts[9]]);
+    Ok(seconds * 1_000_000_000 + nanoseconds as u64)
+}
+
+/// Formats a clock identity the way `a=ts-refclk` does.
+fn clock_identity(id: &[u8]) -> String {
+    id.iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn announce(domain: u8, grandmaster: [u8; 8]) -> Vec<u8> {
+        let mut data = vec![0; 64];
+        data[0] = MSG_ANNOUNCE;
+        data[1] = 2;
+        data[2..4].copy_from_slice(&64u16.to_be_bytes());
+        data[4] = domain;
+        data[20..28].copy_from_slice(&grandmaster);
+        data[44..46].copy_from_slice(&37i16.to_be_bytes());
+        data[47] = 128;
+        data[48] = 6;
+        data[52] = 128;
+        data[53..61].copy_from_slice(&grandmaster);
+        data
+    }
+
+    #[test]
+    fn parse_announce() {
+        let gm = [0x00, 0x1D, 0xC1, 0xFF, 0xFE, 0x0E, 0x10, 0x3C];
+        let (domain, message) = parse(&announce(3, gm)).unwrap();
+        assert_eq!(domain, 3);
+        let PtpMessage::Announce(announce) = message else {
+            panic!("not an announce message");
+        };
+        assert_eq!(announce.grandmaster, "00-1D-C1-FF-FE-0E-10-3C");
+        assert_eq!(announce.utc_offset, 37);
+        assert_eq!(announce.clock_class, 6);
+    }
+
+    #[test]
+    fn reference_clock_status() {
+        let monitor = PtpMonitor::default();
+        let gm = [0x00, 0x1D, 0xC1, 0xFF, 0xFE, 0x0E, 0x10, 0x3C];
+        let (domain, message) = parse(&announce(0, gm)).unwrap();
+        monitor.update(domain, message, SystemTime::now());
+
+        let clock = |grandmaster: &str, domain| ReferenceClock::Ptp {
+            version: "IEEE1588-2008".to_owned(),
+            grandmaster: Some(grandmaster.to_owned()),
+            domain: Some(domain),
+        };
+        assert_eq!(
+            monitor.clock_status(&clock("00-1D-C1-FF-FE-0E-10-3C", 0)),
+            ClockStatus::Locked
+        );
+        assert_eq!(
+            monitor.clock_status(&clock("39-A7-94-FF-FE-07-CB-D0", 0)),
+            ClockStatus::Mismatch {
+                grandmaster: "00-1D-C1-FF-FE-0E-10-3C".to_owned()
+            }
+        );
+        assert_eq!(
+            monitor.clock_status(&clock("00-1D-C1-FF-FE-0E-10-3C", 1)),
+            ClockStatus::NoGrandmaster
+        );
+        assert_eq!(
+            monitor.clock_status(&ReferenceClock::Other(
+                "localmac=00-11-22-33-44-55".to_owned()
+            )),
+            ClockStatus::NotPtp
+        );
+    }
+}
@@ -0,0 +1,562 @@
+use crate::{
+    pcm,
+    ptp::{PtpMonitor, DEFAULT_UTC_OFFSET},
+    stream::{Packet, Stream, StreamHandle},
+    BitDepth, ReferenceClock, SessionDescriptor,
+};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::mpsc,
+    task::{spawn_blocking, JoinHandle},
+};
+
+const BEXT_LEN: usize = 602;
+const ORIGINATOR: &str = "aes67-to-ws";
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const MAX_RIFF_SIZE: u64 = u32::MAX as u64;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecorderConfig {
+    /// Start a new file after this many seconds of audio.
+    #[serde(default)]
+    pub max_duration: Option<f32>,
+    /// Start a new file before a file, headers included, would exceed this many bytes.
+    #[serde(default)]
+    pub max_size: Option<u64>,
+}
+
+impl RecorderConfig {
+    /// Fails unless files of the recorded session can be rotated as configured.
+    pub fn validate(&self, descriptor: &SessionDescriptor) -> anyhow::Result<()> {
+        if self.max_duration.is_some_and(|d| d.is_nan() || d <= 0.0) {
+            return Err(anyhow!("maximum file duration must be positive"));
+        }
+        if let Some(max_size) = self.max_size {
+            let mut sd = descriptor.clone();
+            sd.bit_depth = sd.bit_depth.pcm();
+            let min_size = header_len(&sd) + sd.buffer_size_bytes() as u64;
+            if max_size < min_size {
+                return Err(anyhow!(
+                    "maximum file size must hold the header and a packet, {min_size} bytes"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingInfo {
+    pub name: String,
+    pub descriptor: SessionDescriptor,
+    pub files: Vec<PathBuf>,
+}
+
+struct Recording {
+    stream: StreamHandle,
+    /// Task writing the files, which are finalized once the stream has stopped.
+    writer: JoinHandle<()>,
+    info: Arc<Mutex<RecordingInfo>>,
+}
+
+/// Keeps track of all running recordings by name.
+#[derive(Clone)]
+pub struct RecorderRegistry {
+    directory: PathBuf,
+    recordings: Arc<Mutex<HashMap<String, Recording>>>,
+    max_recordings: Option<usize>,
+    ptp: Option<PtpMonitor>,
+}
+
+impl Default for RecorderRegistry {
+    fn default() -> Self {
+        Self::new("recordings")
+    }
+}
+
+impl RecorderRegistry {
+    /// Creates a registry writing all recorded files to the given directory.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            recordings: Default::default(),
+            max_recordings: None,
+            ptp: None,
+        }
+    }
+
+    /// Limits the number of concurrently running recordings.
+    pub fn with_limit(mut self, max_recordings: Option<usize>) -> Self {
+        self.max_recordings = max_recordings;
+        self
+    }
+
+    /// Takes the UTC offset of the media clocks of PTP sessions from their grandmasters.
+    pub fn with_ptp(mut self, ptp: PtpMonitor) -> Self {
+        self.ptp = Some(ptp);
+        self
+    }
+
+    pub async fn start(
+        &self,
+        name: String,
+        mut stream: Stream,
+        config: RecorderConfig,
+    ) -> anyhow::Result<()> {
+        let sd = stream.descriptor.clone();
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(anyhow!("invalid recording name: {name}"));
+        }
+        config.validate(&sd)?;
+        {
+            let recordings = self.recordings.lock().expect("mutex poisoned");
+            if recordings.contains_key(&name) {
+                return Err(anyhow!("recording '{name}' is already running"));
+            }
+            if let Some(max) = self.max_recordings.filter(|max| recordings.len() >= *max) {
+                return Err(anyhow!("the maximum of {max} recordings is running"));
+            }
+        }
+
+        fs::create_dir_all(&self.directory)?;
+
+        log::info!("Starting recording '{name}' of {sd:?}");
+        let (payload_tx, payload_rx) = mpsc::unbounded_channel();
+        let handle = stream.play(payload_tx).await?;
+
+        let info = Arc::new(Mutex::new(RecordingInfo {
+            name: name.clone(),
+            descriptor: sd,
+            files: Vec::new(),
+        }));
+
+        let domain = match &info
+            .lock()
+            .expect("mutex poisoned")
+            .descriptor
+            .reference_clock
+        {
+            Some(ReferenceClock::Ptp { domain, .. }) => domain.unwrap_or(0),
+            _ => 0,
+        };
+        let utc_offset = self
+            .ptp
+            .as_ref()
+            .and_then(|ptp| ptp.utc_offset(domain))
+            .unwrap_or(DEFAULT_UTC_OFFSET);
+
+        let recording_info = info.clone();
+        let directory = self.directory.clone();
+        let writer = spawn_blocking(move || {
+            if let Err(e) = record(payload_rx, directory, config, recording_info, utc_offset) {
+                log::error!("Error writing recording: {e}");
+            }
+        });
+
+        self.recordings.lock().expect("mutex poisoned").insert(
+            name,
+            Recording {
+                stream: handle,
+                writer,
+                info,
+            },
+        );
+
+        Ok(())
+    }
+
+    pub fn stop(&self, name: &str) -> bool {
+        if let Some(recording) = self.recordings.lock().expect("mutex poisoned").remove(name) {
+            log::info!("Stopping recording '{name}'");
+            recording.stream.stop();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Stops all recordings and waits until their files are finalized.
+    pub async fn stop_all(&self) {
+        let recordings: Vec<_> = self
+            .recordings
+            .lock()
+            .expect("mutex poisoned")
+            .drain()
+            .collect();
+        for (name, mut recording) in recordings {
+            log::info!("Stopping recording '{name}'");
+            recording.stream.join().await;
+            recording.writer.await.ok();
+        }
+    }
+
+    /// The session recorded under the given name.
+    pub fn descriptor(&self, name: &str) -> Option<SessionDescriptor> {
+        let recordings = self.recordings.lock().expect("mutex poisoned");
+        let recording = recordings.get(name)?;
+        let descriptor = recording
+            .info
+            .lock()
+            .expect("mutex poisoned")
+            .descriptor
+            .clone();
+        Some(descriptor)
+    }
+
+    pub fn list(&self) -> Vec<RecordingInfo> {
+        self.recordings
+            .lock()
+            .expect("mutex poisoned")
+            .values()
+            .map(|r| r.info.lock().expect("mutex poisoned").clone())
+            .collect()
+    }
+}
+
+fn record(
+    mut payload_rx: mpsc::UnboundedReceiver<Packet>,
+    directory: PathBuf,
+    config: RecorderConfig,
+    info: Arc<Mutex<RecordingInfo>>,
+    utc_offset: i16,
+) -> anyhow::Result<()> {
+    let (name, mut sd) = {
+        let info = info.lock().expect("mutex poisoned");
+        (info.name.clone(), info.descriptor.clone())
+    };
+    // WAV files hold little endian samples of whole bytes, which are swapped from big endian
+    let encoding = sd.bit_depth.clone();
+    sd.bit_depth = encoding.pcm();
+
+    let bytes_per_frame = (sd.bit_depth.bits() / 8) as u64 * sd.channels as u64;
+    let max_frames = config
+        .max_duration
+        .map(|d| (d as f64 * sd.sample_rate as f64) as u64)
+        .unwrap_or(u64::MAX);
+    let max_size = config.max_size.unwrap_or(MAX_RIFF_SIZE).min(MAX_RIFF_SIZE);
+
+    let mut start_time = None;
+    let mut frames_recorded = 0;
+    let mut file_index = 0;
+    let mut writer: Option<BwfWriter> = None;
+
+    while let Some(Packet {
+        payload,
+        media_time,
+        ..
+    }) = payload_rx.blocking_recv()
+    {
+        let payload = pcm::convert(&payload, &encoding, &sd.bit_depth);
+        // prefer the sender's media clock over the time of arrival for the time reference
+        let start_time = *start_time.get_or_insert_with(|| {
+            media_time
+                .map(|t| utc_from_media_time(t, utc_offset))
+                .unwrap_or_else(SystemTime::now)
+        });
+
+        let rotate = writer
+            .as_ref()
+            .is_some_and(|w| w.frames() >= max_frames || w.len() + payload.len() as u64 > max_size);
+        if rotate {
+            if let Some(w) = writer.take() {
+                w.finalize()?;
+            }
+        }
+
+        let w = match &mut writer {
+            Some(w) => w,
+            None => {
+                let path = directory.join(format!("{name}_{file_index:04}.wav"));
+                file_index += 1;
+                log::info!("Recording to {}", path.display());
+                let new_writer = BwfWriter::create(&path, &sd, start_time, frames_recorded)?;
+                info.lock().expect("mutex poisoned").files.push(path);
+                writer.insert(new_writer)
+            }
+        };
+
+        w.write_payload(&payload)?;
+        frames_recorded += payload.len() as u64 / bytes_per_frame;
+    }
+
+    if let Some(w) = writer {
+        w.finalize()?;
+    }
+
+    log::info!("Recording '{name}' closed.");
+    Ok(())
+}
+
+struct BwfWriter {
+    file: BufWriter<File>,
+    bit_depth: BitDepth,
+    bytes_per_frame: u64,
+    data_size_position: u64,
+    data_len: u64,
+}
+
+impl BwfWriter {
+    /// Creates a new file whose time reference lies `offset_frames` after `start_time`.
+    fn create(
+        path: &Path,
+        sd: &SessionDescriptor,
+        start_time: SystemTime,
+        offset_frames: u64,
+    ) -> anyhow::Result<Self> {
+        let bytes_per_sample = sd.bit_depth.bits() / 8;
+        let bytes_per_frame = bytes_per_sample as u64 * sd.channels as u64;
+        let format_tag = if sd.bit_depth.floating_point() {
+            WAVE_FORMAT_IEEE_FLOAT
+        } else {
+            WAVE_FORMAT_PCM
+        };
+
+        let mut header = Vec::new();
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+
+        let bext = bext_chunk(sd, start_time, offset_frames);
+        header.extend_from_slice(b"bext");
+        header.extend_from_slice(&(bext.len() as u32).to_le_bytes());
+        header.extend_from_slice(&bext);
+        if bext.len() % 2 == 1 {
+            header.push(0);
+        }
+
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&format_tag.to_le_bytes());
+        header.extend_from_slice(&sd.channels.to_le_bytes());
+        header.extend_from_slice(&sd.sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sd.sample_rate * bytes_per_frame as u32).to_le_bytes());
+        header.extend_from_slice(&(bytes_per_frame as u16).to_le_bytes());
+        header.extend_from_slice(&sd.bit_depth.bits().to_le_bytes());
+
+        header.extend_from_slice(b"data");
+        let data_size_position = header.len() as u64;
+        header.extend_from_slice(&0u32.to_le_bytes());
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&header)?;
+
+        Ok(BwfWriter {
+            file,
+            bit_depth: sd.bit_depth.clone(),
+            bytes_per_frame,
+            data_size_position,
+            data_len: 0,
+        })
+    }
+
+    fn frames(&self) -> u64 {
+        self.data_len / self.bytes_per_frame
+    }
+
+    /// Bytes written to the file so far.
+    fn len(&self) -> u64 {
+        self.data_size_position + 4 + self.data_len
+    }
+
+    /// RTP payloads are big endian while WAV data is little endian, so every sample is byte
+    /// swapped on its way to the file.
+    fn write_payload(&mut self, payload: &[u8]) -> anyhow::Result<()> {
+        let bytes_per_sample = (self.bit_depth.bits() / 8) as usize;
+        let mut data = payload.to_owned();
+        for sample in data.chunks_exact_mut(bytes_per_sample) {
+            sample.reverse();
+        }
+        self.file.write_all(&data)?;
+        self.data_len += data.len() as u64;
+        Ok(())
+    }
+
+    fn finalize(mut self) -> anyhow::Result<()> {
+        if self.data_len % 2 == 1 {
+            self.file.write_all(&[0])?;
+        }
+        let riff_size = self.data_size_position + 4 + self.data_len + self.data_len % 2 - 8;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(riff_size as u32).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(self.data_size_position))?;
+        self.file.write_all(&(self.data_len as u32).to_le_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Length of the headers written by [`BwfWriter::create`] before the audio data.
+fn header_len(sd: &SessionDescriptor) -> u64 {
+    let bext_len = bext_chunk(sd, UNIX_EPOCH, 0).len() as u64;
+    12 + 8 + bext_len + bext_len % 2 + 8 + 16 + 8
+}
+
+/// Converts a media time in nanoseconds since the PTP epoch, which counts TAI, to UTC.
+fn utc_from_media_time(media_time: u64, utc_offset: i16) -> SystemTime {
+    let utc = media_time as i128 - utc_offset as i128 * 1_000_000_000;
+    UNIX_EPOCH + Duration::from_nanos(utc.max(0) as u64)
+}
+
+fn bext_chunk(sd: &SessionDescriptor, start_time: SystemTime, offset_frames: u64) -> Vec<u8> {
+    let since_epoch = start_time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let seconds_of_day = secs % 86_400;
+
+    // the host clock is expected to be disciplined by PTP, which makes the time of day of the
+    // first received packet the PTP derived start time of the recording
+    let time_reference = ((seconds_of_day as f64 + since_epoch.subsec_nanos() as f64 * 1e-9)
+        * sd.sample_rate as f64) as u64
+        + offset_frames;
+
+    let description = sd.session_name.as_deref().unwrap_or_default();
+
+    let mut bext = Vec::with_capacity(BEXT_LEN);
+    put_fixed(&mut bext, description, 256);
+    put_fixed(&mut bext, ORIGINATOR, 32);
+    put_fixed(&mut bext, description, 32);
+    put_fixed(&mut bext, &format!("{year:04}-{month:02}-{day:02}"), 10);
+    put_fixed(
+        &mut bext,
+        &format!(
+            "{:02}:{:02}:{:02}",
+            seconds_of_day / 3600,
+            seconds_of_day / 60 % 60,
+            seconds_of_day % 60
+        ),
+        8,
+    );
+    bext.extend_from_slice(&(time_reference as u32).to_le_bytes());
+    bext.extend_from_slice(&((time_reference >> 32) as u32).to_le_bytes());
+    bext.extend_from_slice(&1u16.to_le_bytes());
+    bext.resize(BEXT_LEN, 0);
+
+    let mode = match sd.channels {
+        1 => "mono",
+        2 => "stereo",
+        _ => "multitrack",
+    };
+    bext.extend_from_slice(
+        format!(
+            "A=PCM,F={},W={},M={mode},T={ORIGINATOR}\r\n",
+            sd.sample_rate,
+            sd.bit_depth.bits()
+        )
+        .as_bytes(),
+    );
+
+    bext
+}
+
+fn put_fixed(buf: &mut Vec<u8>, value: &str, len: usize) {
+    let bytes = value.as_bytes();
+    let n = bytes.len().min(len);
+    buf.extend_from_slice(&bytes[..n]);
+    buf.resize(buf.len() + len - n, 0);
+}
+
+/// Converts days since the unix epoch to a (year, month, day) date in the proleptic Gregorian
+/// calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate_rotation() {
+        let sd = SessionDescriptor::default();
+        assert!(RecorderConfig::default().validate(&sd).is_ok());
+        let packet = sd
+            .bit_depth
+            .pcm()
+            .bytes(sd.buffer_size_frames() as usize * 2) as u64;
+        let min_size = header_len(&sd) + packet;
+        let path = std::env::temp_dir().join("aes67-to-ws-header-len.wav");
+        let writer = BwfWriter::create(&path, &sd, SystemTime::now(), 0).unwrap();
+        assert_eq!(writer.len(), header_len(&sd));
+        drop(writer);
+        fs::remove_file(path).ok();
+        let fits = RecorderConfig {
+            max_size: Some(min_size),
+            ..Default::default()
+        };
+        assert!(fits.validate(&sd).is_ok());
+        for config in [
+            RecorderConfig {
+                max_duration: Some(0.0),
+                ..Default::default()
+            },
+            RecorderConfig {
+                max_size: Some(0),
+                ..Default::default()
+            },
+            RecorderConfig {
+                max_size: Some(min_size - 1),
+                ..Default::default()
+            },
+        ] {
+            assert!(config.validate(&sd).is_err());
+        }
+    }
+
+    #[test]
+    fn media_time_to_utc() {
+        // 2024-02-29 01:00:00 UTC is 01:00:37 TAI
+        let tai = (19_782 * 86_400 + 3_637) * 1_000_000_000;
+        let utc = utc_from_media_time(tai, DEFAULT_UTC_OFFSET);
+        let bext = bext_chunk(&SessionDescriptor::default(), utc, 0);
+        assert_eq!(&bext[320..330], b"2024-02-29");
+        assert_eq!(&bext[330..338], b"01:00:00");
+        assert_eq!(
+            utc.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            19_782 * 86_400 + 3_600
+        );
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    }
+
+    #[test]
+    fn bext_layout() {
+        let sd = SessionDescriptor {
+            sample_rate: 48_000,
+            session_name: Some("Studio 1".to_owned()),
+            ..Default::default()
+        };
+        let start = UNIX_EPOCH + Duration::from_secs(19_782 * 86_400 + 3_600);
+        let bext = bext_chunk(&sd, start, 48_000);
+        assert_eq!(&bext[0..8], b"Studio 1");
+        assert_eq!(&bext[320..330], b"2024-02-29");
+        assert_eq!(&bext[330..338], b"01:00:00");
+        let time_reference = u32::from_le_bytes(bext[338..342].try_into().unwrap());
+        assert_eq!(time_reference, 3_601 * 48_000);
+        assert!(bext[BEXT_LEN..].starts_with(b"A=PCM,F=48000,W=16,M=stereo"));
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::anyhow;
+use std::f64::consts::PI;
+
+/// Half the number of input frames contributing to each output frame.
+const TAPS: usize = 32;
+/// Number of precomputed filter phases between two input frames.
+const PHASES: usize = 256;
+/// Passband edge relative to the lower of the two Nyquist frequencies.
+const BANDWIDTH: f64 = 0.95;
+const MAX_SAMPLE_RATE: u32 = 384_000;
+
+/// Band limited sample rate converter using a Blackman windowed sinc filter, interpolated
+/// between precomputed phases so that the ratio can be adjusted continuously.
+pub struct Resampler {
+    channels: usize,
+    /// Input frames consumed per output frame at the nominal rates.
+    nominal_step: f64,
+    step: f64,
+    table: Vec<[f32; 2 * TAPS]>,
+    buffer: Vec<f32>,
+    /// Position of the next output frame in the buffer, in input frames.
+    position: f64,
+}
+
+impl Resampler {
+    pub fn new(channels: u16, input_rate: u32, output_rate: u32) -> anyhow::Result<Self> {
+        if channels == 0 {
+            return Err(anyhow!("cannot resample zero channels"));
+        }
+        for rate in [input_rate, output_rate] {
+            if rate == 0 || rate > MAX_SAMPLE_RATE {
+                return Err(anyhow!("unsupported sample rate: {rate}"));
+            }
+        }
+
+        let channels = channels as usize;
+        let nominal_step = input_rate as f64 / output_rate as f64;
+        let cutoff = BANDWIDTH * (1.0 / nominal_step).min(1.0);
+        let table = (0..=PHASES)
+            .map(|phase| {
+                let fraction = phase as f64 / PHASES as f64;
+                let mut coefficients = [0.0; 2 * TAPS];
+                for (j, c) in coefficients.iter_mut().enumerate() {
+                    *c = kernel(fraction + TAPS as f64 - 1.0 - j as f64, cutoff) as f32;
+                }
+                coefficients
+            })
+            .collect();
+
+        Ok(Resampler {
+            channels,
+            nominal_step,
+            step: nominal_step,
+            table,
+            buffer: vec![0.0; TAPS * channels],
+            position: TAPS as f64,
+        })
+    }
+
+    /// Compensates for the input's clock running `ppm` fast relative to the output clock.
+    pub fn set_drift(&mut self, ppm: f64) {
+        self.step = self.nominal_step * (1.0 + ppm / 1_000_000.0);
+    }
+
+    /// Resamples interleaved frames, returning all output frames that can be computed so far.
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let channels = self.channels;
+        self.buffer
+            .extend_from_slice(&input[..input.len() - input.len() % channels]);
+        let frames = self.buffer.len() / channels;
+
+        let mut output = Vec::with_capacity(
+            ((input.len() / channels) as f64 / self.step + 1.0) as usize * channels,
+        );
+        while (self.position as usize) + TAPS < frames {
+            let index = self.position as usize;
+            let phase = (self.position - index as f64) * PHASES as f64;
+            let (phase, weight) = (phase as usize, (phase - phase.floor()) as f32);
+            let (a, b) = (&self.table[phase], &self.table[phase + 1]);
+
+            let first = index + 1 - TAPS;
+            for channel in 0..channels {
+                let mut sum = 0.0;
+                for j in 0..2 * TAPS {
+                    let coefficient = a[j] + (b[j] - a[j]) * weight;
+                    sum += self.buffer[(first + j) * channels + channel] * coefficient;
+                }
+                output.push(sum);
+            }
+            self.position += self.step;
+        }
+
+        // keep the history needed for the next output frame
+        let consumed = (self.position as usize + 1)
+            .saturating_sub(TAPS)
+            .min(frames);
+        self.buffer.drain(..consumed * channels);
+        self.position -= consumed as f64;
+
+        output
+    }
+}
+
+fn kernel(x: f64, cutoff: f64) -> f64 {
+    if x.abs() >= TAPS as f64 {
+        return 0.0;
+    }
+    let sinc = if x == 0.0 {
+        1.0
+    } else {
+        (PI * cutoff * x).sin() / (PI * cutoff * x)
+    };
+    let window =
+        0.42 + 0.5 * (PI * x / TAPS as f64).cos() + 0.08 * (2.0 * PI * x / TAPS as f64).cos();
+    cutoff * sinc * window
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sine(frequency: f64, rate: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|i| {
+                let v = (2.0 * PI * frequency * i as f64 / rate).sin() as f32 * 0.5;
+                [v, -v]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn convert_sine() {
+        let mut resampler = Resampler::new(2, 48_000, 44_100).unwrap();
+        let input = sine(1_000.0, 48_000.0, 48_000);
+        let output: Vec<f32> = input
+            .chunks(96)
+            .flat_map(|packet| resampler.process(packet))
+            .collect();
+
+        let frames = output.len() / 2;
+        assert!(
+            (44_100 - TAPS..=44_100).contains(&frames),
+            "{frames} frames"
+        );
+
+        // away from the edges the output matches the ideal one
+        let expected = sine(1_000.0, 44_100.0, frames);
+        let error = (1_000..frames - 1_000)
+            .map(|i| (output[i * 2] - expected[i * 2]).abs())
+            .fold(0.0, f32::max);
+        assert!(error < 0.02, "error {error}");
+        assert_eq!(output[2_000], -output[2_001]);
+    }
+
+    #[test]
+    fn rejects_unsupported_rates() {
+        assert!(Resampler::new(2, 48_000, 0).is_err());
+        assert!(Resampler::new(0, 48_000, 44_100).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+const RTCP_VERSION: u8 = 2;
+const PT_SENDER_REPORT: u8 = 200;
+const PT_RECEIVER_REPORT: u8 = 201;
+const PT_SOURCE_DESCRIPTION: u8 = 202;
+const PT_GOODBYE: u8 = 203;
+const SDES_END: u8 = 0;
+const SDES_CNAME: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SenderReport {
+    pub ssrc: u32,
+    /// 64 bit NTP timestamp (32.32 fixed point seconds since 1900).
+    pub ntp_timestamp: u64,
+    /// RTP timestamp corresponding to the NTP timestamp.
+    pub rtp_timestamp: u32,
+    pub packet_count: u32,
+    pub octet_count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RtcpPacket {
+    SenderReport(SenderReport),
+    SourceDescription {
+        ssrc: u32,
+        cname: Option<String>,
+    },
+    Goodbye {
+        ssrcs: Vec<u32>,
+        reason: Option<String>,
+    },
+    Other(u8),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportBlock {
+    pub ssrc: u32,
+    pub fraction_lost: u8,
+    pub cumulative_lost: u32,
+    pub extended_highest_sequence_number: u32,
+    pub jitter: u32,
+    pub last_sender_report: u32,
+    pub delay_since_last_sender_report: u32,
+}
+
+/// Parses all packets of a compound RTCP packet.
+pub fn parse(mut data: &[u8]) -> anyhow::Result<Vec<RtcpPacket>> {
+    let mut packets = Vec::new();
+
+    while data.len() >= 4 {
+        let version = data[0] >> 6;
+        if version != RTCP_VERSION {
+            return Err(anyhow!("unsupported RTCP version: {version}"));
+        }
+        let count = data[0] & 0x1F;
+        let packet_type = data[1];
+        let len = (u16::from_be_bytes([data[2], data[3]]) as usize + 1) * 4;
+        let packet = data
+            .get(4..len)
+            .ok_or_else(|| anyhow!("truncated RTCP packet"))?;
+        // padding only ever applies to the last packet of a compound packet
+        let packet = if data[0] & 0x20 != 0 && len == data.len() {
+            let padding = *packet.last().unwrap_or(&0) as usize;
+            &packet[..packet.len().saturating_sub(padding)]
+        } else {
+            packet
+        };
+
+        packets.extend(match packet_type {
+            PT_SENDER_REPORT => parse_sender_report(packet)?,
+            PT_SOURCE_DESCRIPTION => parse_source_description(packet, count),
+            PT_GOODBYE => parse_goodbye(packet, count),
+            other => vec![RtcpPacket::Other(other)],
+        });
+
+        data = &data[len..];
+    }
+
+    Ok(packets)
+}
+
+fn parse_sender_report(packet: &[u8]) -> anyhow::Result<Vec<RtcpPacket>> {
+    if packet.len() < 24 {
+        return Err(anyhow!("truncated RTCP sender report"));
+    }
+    Ok(vec![RtcpPacket::SenderReport(SenderReport {
+        ssrc: u32_at(packet, 0),
+        ntp_timestamp: (u32_at(packet, 4) as u64) << 32 | u32_at(packet, 8) as u64,
+        rtp_timestamp: u32_at(packet, 12),
+        packet_count: u32_at(packet, 16),
+        octet_count: u32_at(packet, 20),
+    })])
+}
+
+fn parse_source_description(mut packet: &[u8], count: u8) -> Vec<RtcpPacket> {
+    let mut chunks = Vec::new();
+
+    for _ in 0..count {
+        if packet.len() < 4 {
+            break;
+        }
+        let ssrc = u32_at(packet, 0);
+        let mut cname = None;
+        let mut offset = 4;
+        while let Some(&item_type) = packet.get(offset) {
+            if item_type == SDES_END {
+                offset += 1;
+                break;
+            }
+            let Some(&len) = packet.get(offset + 1) else {
+                break;
+            };
+            let value = packet.get(offset + 2..offset + 2 + len as usize);
+            if item_type == SDES_CNAME {
+                cname = value.map(|v| String::from_utf8_lossy(v).into_owned());
+            }
+            offset += 2 + len as usize;
+        }
+        // chunks are padded to a multiple of 4 bytes
+        offset = (offset + 3) & !3;
+        chunks.push(RtcpPacket::SourceDescription { ssrc, cname });
+        packet = packet.get(offset..).unwrap_or_default();
+    }
+
+    chunks
+}
+
+fn parse_goodbye(packet: &[u8], count: u8) -> Vec<RtcpPacket> {
+    let ssrcs: Vec<u32> = (0..count as usize)
+        .take_while(|i| packet.len() >= (i + 1) * 4)
+        .map(|i| u32_at(packet, i * 4))
+        .collect();
+    let reason = packet.get(ssrcs.len() * 4).and_then(|len| {
+        let start = ssrcs.len() * 4 + 1;
+        packet
+            .get(start..start + *len as usize)
+            .map(|r| String::from_utf8_lossy(r).into_owned())
+    });
+    vec![RtcpPacket::Goodbye { ssrcs, reason }]
+}
+
+/// Builds a receiver report packet followed by an SDES packet carrying the CNAME, as required
+/// for compound RTCP packets.
+pub fn receiver_report(ssrc: u32, cname: &str, block: Option<&ReportBlock>) -> Vec<u8> {
+    let mut packet = Vec::new();
+
+    let words = 1 + if block.is_some() { 6 } else { 0 };
+    packet.push(RTCP_VERSION << 6 | block.is_some() as u8);
+    packet.push(PT_RECEIVER_REPORT);
+    packet.extend_from_slice(&(words as u16).to_be_bytes());
+    packet.extend_from_slice(&ssrc.to_be_bytes());
+    if let Some(block) = block {
+        packet.extend_from_slice(&block.ssrc.to_be_bytes());
+        packet.push(block.fraction_lost);
+        packet.extend_from_slice(&block.cumulative_lost.min(0x7F_FFFF).to_be_bytes()[1..]);
+        packet.extend_from_slice(&block.extended_highest_sequence_number.to_be_bytes());
+        packet.extend_from_slice(&block.jitter.to_be_bytes());
+        packet.extend_from_slice(&block.last_sender_report.to_be_bytes());
+        packet.extend_from_slice(&block.delay_since_last_sender_report.to_be_bytes());
+    }
+
+    let cname = &cname.as_bytes()[..cname.len().min(255)];
+    let chunk_len = 4 + 2 + cname.len() + 1;
+    let padded_len = (chunk_len + 3) & !3;
+    packet.push(RTCP_VERSION << 6 | 1);
+    packet.push(PT_SOURCE_DESCRIPTION);
+    packet.extend_from_slice(&((padded_len / 4) as u16).to_be_bytes());
+    packet.extend_from_slice(&ssrc.to_be_bytes());
+    packet.push(SDES_CNAME);
+    packet.push(cname.len() as u8);
+    packet.extend_from_slice(cname);
+    packet.resize(packet.len() + padded_len - chunk_len + 1, SDES_END);
+
+    packet
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_compound_packet() {
+        let mut data = vec![0x80, PT_SENDER_REPORT, 0, 6];
+        data.extend_from_slice(&0x1234u32.to_be_bytes());
+        data.extend_from_slice(&0xE000_0000_8000_0000u64.to_be_bytes());
+        data.extend_from_slice(&48_000u32.to_be_bytes());
+        data.extend_from_slice(&100u32.to_be_bytes());
+        data.extend_from_slice(&19_200u32.to_be_bytes());
+        data.extend_from_slice(&[0x81, PT_GOODBYE, 0, 2]);
+        data.extend_from_slice(&0x1234u32.to_be_bytes());
+        data.extend_from_slice(&[2, b'o', b'k', 0]);
+
+        let packets = parse(&data).unwrap();
+        assert_eq!(
+            packets,
+            vec![
+                RtcpPacket::SenderReport(SenderReport {
+                    ssrc: 0x1234,
+                    ntp_timestamp: 0xE000_0000_8000_0000,
+                    rtp_timestamp: 48_000,
+                    packet_count: 100,
+                    octet_count: 19_200,
+                }),
+                RtcpPacket::Goodbye {
+                    ssrcs: vec![0x1234],
+                    reason: Some("ok".to_owned())
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn receiver_report_roundtrip() {
+        let block = ReportBlock {
+            ssrc: 0x1234,
+            fraction_lost: 0,
+            cumulative_lost: 3,
+            extended_highest_sequence_number: 70_000,
+            jitter: 12,
+            last_sender_report: 0,
+            delay_since_last_sender_report: 0,
+        };
+        let packet = receiver_report(0xABCD, "aes67-to-ws", Some(&block));
+        assert_eq!(packet.len() % 4, 0);
+        let packets = parse(&packet).unwrap();
+        assert_eq!(
+            packets,
+            vec![
+                RtcpPacket::Other(PT_RECEIVER_REPORT),
+                RtcpPacket::SourceDescription {
+                    ssrc: 0xABCD,
+                    cname: Some("aes67-to-ws".to_owned())
+                }
+            ]
+        );
+    }
+}
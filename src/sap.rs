@@ -0,0 +1,276 @@
+use anyhow::anyhow;
+use socket2::{Domain, Socket, Type};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    select, spawn,
+    sync::broadcast,
+    time::{interval, Instant},
+};
+
+use crate::SessionDescriptor;
+
+pub const SAP_ADDRESS: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 255);
+pub const SAP_PORT: u16 = 9875;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30);
+const PAYLOAD_TYPE: &[u8] = b"application/sdp\0";
+/// Announced sessions are forgotten if not announced again within this time, ten times the
+/// usual announcement interval.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Builds an RFC 2974 SAP packet announcing (or deleting) the given SDP.
+pub fn packet(origin: Ipv4Addr, sdp: &str, deletion: bool) -> Vec<u8> {
+    // version 1, IPv4 origin, no encryption or compression
+    let flags = 0b0010_0000 | if deletion { 0b0000_0100 } else { 0 };
+    let message_id_hash = sdp
+        .bytes()
+        .fold(0u16, |hash, b| hash.rotate_left(5) ^ b as u16)
+        .max(1);
+
+    let mut packet = Vec::with_capacity(8 + PAYLOAD_TYPE.len() + sdp.len());
+    packet.push(flags);
+    packet.push(0);
+    packet.extend_from_slice(&message_id_hash.to_be_bytes());
+    packet.extend_from_slice(&origin.octets());
+    packet.extend_from_slice(PAYLOAD_TYPE);
+    packet.extend_from_slice(sdp.as_bytes());
+    packet
+}
+
+/// Periodically announces the SDP from the interface with the given local address until
+/// stopped, then sends a deletion.
+pub async fn announce(
+    local_address: Ipv4Addr,
+    origin: Ipv4Addr,
+    sdp: String,
+    stop: broadcast::Sender<()>,
+) {
+    let mut stop = stop.subscribe();
+    let destination = SocketAddrV4::new(SAP_ADDRESS, SAP_PORT);
+
+    let socket = match announce_socket(local_address) {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::error!("Could not open SAP socket: {e}");
+            return;
+        }
+    };
+
+    let mut ticks = interval(ANNOUNCE_INTERVAL);
+    loop {
+        select! {
+            _ = stop.recv() => break,
+            _ = ticks.tick() => {
+                if let Err(e) = socket.send_to(&packet(origin, &sdp, false), destination).await {
+                    log::warn!("Could not send SAP announcement: {e}");
+                }
+            }
+        }
+    }
+
+    if let Err(e) = socket
+        .send_to(&packet(origin, &sdp, true), destination)
+        .await
+    {
+        log::warn!("Could not send SAP deletion: {e}");
+    }
+}
+
+/// A received SAP packet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Announcement {
+    pub origin: IpAddr,
+    pub message_id_hash: u16,
+    pub deletion: bool,
+    pub sdp: String,
+}
+
+/// Parses an unencrypted, uncompressed RFC 2974 SAP packet carrying SDP.
+pub fn parse(packet: &[u8]) -> anyhow::Result<Announcement> {
+    let truncated = || anyhow!("truncated SAP packet");
+    let flags = *packet.first().ok_or_else(truncated)?;
+    if flags >> 5 != 1 {
+        return Err(anyhow!("unsupported SAP version {}", flags >> 5));
+    }
+    if flags & 0b0000_0011 != 0 {
+        return Err(anyhow!("encrypted or compressed SAP packet"));
+    }
+    let authentication_len = *packet.get(1).ok_or_else(truncated)? as usize * 4;
+    let message_id_hash = u16::from_be_bytes(packet.get(2..4).ok_or_else(truncated)?.try_into()?);
+    let (origin, origin_len) = if flags & 0b0001_0000 == 0 {
+        let octets: [u8; 4] = packet.get(4..8).ok_or_else(truncated)?.try_into()?;
+        (IpAddr::from(Ipv4Addr::from(octets)), 4)
+    } else {
+        let octets: [u8; 16] = packet.get(4..20).ok_or_else(truncated)?.try_into()?;
+        (IpAddr::from(Ipv6Addr::from(octets)), 16)
+    };
+
+    let mut payload = packet
+        .get(4 + origin_len + authentication_len..)
+        .ok_or_else(truncated)?;
+    // the payload type is optional, SDP is assumed without it
+    if !payload.starts_with(b"v=0") {
+        let end = payload
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| anyhow!("SAP packet without payload type"))?;
+        if &payload[..=end] != PAYLOAD_TYPE {
+            return Err(anyhow!(
+                "unsupported SAP payload type {}",
+                String::from_utf8_lossy(&payload[..end])
+            ));
+        }
+        payload = &payload[end + 1..];
+    }
+
+    Ok(Announcement {
+        origin,
+        message_id_hash,
+        deletion: flags & 0b0000_0100 != 0,
+        sdp: String::from_utf8(payload.to_vec())?,
+    })
+}
+
+struct AnnouncedSession {
+    descriptor: SessionDescriptor,
+    announced: Instant,
+}
+
+/// Tracks the sessions announced via SAP on the network.
+#[derive(Clone, Default)]
+pub struct SessionDirectory {
+    sessions: Arc<Mutex<HashMap<(IpAddr, u16), AnnouncedSession>>>,
+}
+
+impl SessionDirectory {
+    /// Starts listening for SAP announcements. Failing to do so is not fatal, the directory
+    /// will just stay empty.
+    pub fn start(local_address: Ipv4Addr) -> Self {
+        let directory = SessionDirectory::default();
+        match open_socket(local_address) {
+            Ok(socket) => {
+                spawn(directory.clone().listen(socket));
+            }
+            Err(e) => log::warn!("Could not listen for SAP announcements: {e}"),
+        }
+        directory
+    }
+
+    async fn listen(self, socket: UdpSocket) {
+        let mut buf = [0; 4096];
+        loop {
+            let len = match socket.recv(&mut buf).await {
+                Ok(len) => len,
+                Err(e) => {
+                    log::error!("Error receiving SAP announcement: {e}");
+                    break;
+                }
+            };
+            match parse(&buf[..len]) {
+                Ok(announcement) => self.update(announcement),
+                Err(e) => log::debug!("Ignoring SAP packet: {e}"),
+            }
+        }
+    }
+
+    fn update(&self, announcement: Announcement) {
+        let key = (announcement.origin, announcement.message_id_hash);
+        let mut sessions = self.sessions.lock().expect("mutex poisoned");
+        if announcement.deletion {
+            sessions.remove(&key);
+            return;
+        }
+        match announcement.sdp.parse::<SessionDescriptor>() {
+            Ok(descriptor) => {
+                let announced = Instant::now();
+                sessions.insert(
+                    key,
+                    AnnouncedSession {
+                        descriptor,
+                        announced,
+                    },
+                );
+            }
+            Err(e) => log::debug!("Ignoring announced session: {e}"),
+        }
+    }
+
+    /// The sessions currently announced.
+    pub fn sessions(&self) -> Vec<SessionDescriptor> {
+        let mut sessions = self.sessions.lock().expect("mutex poisoned");
+        sessions.retain(|_, s| s.announced.elapsed() < SESSION_TIMEOUT);
+        sessions.values().map(|s| s.descriptor.clone()).collect()
+    }
+
+    /// Whether a session is currently announced on the given group and port.
+    pub fn announces(&self, group: Ipv4Addr, port: u16) -> bool {
+        self.sessions()
+            .iter()
+            .any(|s| s.multicast_address == group && s.multicast_port == port)
+    }
+}
+
+fn announce_socket(local_address: Ipv4Addr) -> anyhow::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
+    if !local_address.is_unspecified() {
+        socket.set_multicast_if_v4(&local_address)?;
+    }
+    socket.bind(&SocketAddrV4::new(local_address, 0).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+fn open_socket(local_address: Ipv4Addr) -> anyhow::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
+    socket.set_reuse_address(true)?;
+    socket.join_multicast_v4(&SAP_ADDRESS, &local_address)?;
+    socket.bind(&SocketAddrV4::new(SAP_ADDRESS, SAP_PORT).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packet_layout() {
+        let sdp = "v=0\n";
+        let packet = packet(Ipv4Addr::new(10, 0, 0, 1), sdp, true);
+        assert_eq!(packet[0], 0x24);
+        assert_eq!(packet[1], 0);
+        assert_eq!(&packet[4..8], &[10, 0, 0, 1]);
+        assert!(packet[8..].starts_with(b"application/sdp\0v=0"));
+    }
+
+    #[test]
+    fn parse_announcement() {
+        let sdp = "v=0\r\no=- 1 1 IN IP4 10.0.0.1\r\ns=Stage\r\nc=IN IP4 239.69.3.4/32\r\n\
+            t=0 0\r\nm=audio 5004 RTP/AVP 97\r\na=rtpmap:97 L24/48000/2\r\na=ptime:1\r\n";
+        let announcement = parse(&packet(Ipv4Addr::new(10, 0, 0, 1), sdp, false)).unwrap();
+        assert_eq!(announcement.origin, IpAddr::from([10, 0, 0, 1]));
+        assert!(!announcement.deletion);
+        assert_eq!(announcement.sdp, sdp);
+
+        // no payload type, 4 bytes of authentication data
+        let mut packet = vec![0x20, 1, 0x12, 0x34, 10, 0, 0, 1, 0, 0, 0, 0];
+        packet.extend_from_slice(sdp.as_bytes());
+        assert_eq!(parse(&packet).unwrap().sdp, sdp);
+        assert!(parse(&packet[..6]).is_err());
+
+        let directory = SessionDirectory::default();
+        directory.update(announcement.clone());
+        assert!(directory.announces(Ipv4Addr::new(239, 69, 3, 4), 5004));
+        assert!(!directory.announces(Ipv4Addr::new(239, 69, 3, 4), 5006));
+        directory.update(Announcement {
+            deletion: true,
+            ..announcement
+        });
+        assert!(directory.sessions().is_empty());
+    }
+}
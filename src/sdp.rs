@@ -1,7 +1,11 @@
-use crate::{BitDepth, SessionDescriptor};
+use crate::{BitDepth, ReferenceClock, SessionDescriptor};
 use anyhow::anyhow;
 use regex::Regex;
-use std::{net::Ipv4Addr, str::FromStr};
+use std::{
+    fmt::{self, Write},
+    net::Ipv4Addr,
+    str::FromStr,
+};
 
 const RTPMAP_REGEX: &str = r"rtpmap:([0-9]+) (.+)\/([0-9]+)\/([0-9]+)";
 const RTPMAP_PAYLOAD_ID_GROUPT: usize = 1;
@@ -18,8 +22,19 @@ const MEDIA_AND_TRANSPORT_PAYLOAD_ID_GROUP: usize = 4;
 const CONNECTION_INFO_REGEX: &str = r"(.+) (IP[4,6]) ([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)\/([0-9]+)";
 const CONNECTION_INFO_MULTICAST_GROUP: usize = 3;
 
-const PTIME_REGEX: &str = r"ptime:(.+)";
+const PAYLOAD_ID: u16 = 98;
+const DEFAULT_SESSION_NAME: &str = "aes67-to-ws";
+
+const PTIME_REGEX: &str = r"^ptime:(.+)";
+const MAXPTIME_REGEX: &str = r"^maxptime:(.+)";
 const PTIME_GROUP: usize = 1;
+const FRAMECOUNT_REGEX: &str = r"^framecount:([0-9]+)";
+const FRAMECOUNT_GROUP: usize = 1;
+
+const TS_REFCLK_PREFIX: &str = "ts-refclk:";
+const MEDIACLK_REGEX: &str = r"^mediaclk:direct=([0-9]+)";
+const MEDIACLK_OFFSET_GROUP: usize = 1;
+const PTP_TRACEABLE: &str = "traceable";
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct RtpMap {
@@ -147,19 +162,102 @@ impl FromStr for ConnectionInfo {
     }
 }
 
-fn parse_packet_time(attribute: &str) -> anyhow::Result<f32> {
-    let re = Regex::new(PTIME_REGEX).expect("cannot fail");
+fn parse_packet_time(attribute: &str, regex: &str) -> anyhow::Result<f32> {
+    let re = Regex::new(regex).expect("cannot fail");
     if let Some(caps) = re.captures(attribute) {
         Ok(caps
             .get(PTIME_GROUP)
             .expect("must exist in matches")
             .as_str()
+            .trim()
             .parse()?)
     } else {
         Err(anyhow!("malformed ptime: {attribute}"))
     }
 }
 
+fn parse_frame_count(attribute: &str) -> anyhow::Result<u32> {
+    let re = Regex::new(FRAMECOUNT_REGEX).expect("cannot fail");
+    if let Some(caps) = re.captures(attribute) {
+        Ok(caps
+            .get(FRAMECOUNT_GROUP)
+            .expect("must exist in matches")
+            .as_str()
+            .parse()?)
+    } else {
+        Err(anyhow!("malformed framecount: {attribute}"))
+    }
+}
+
+fn parse_reference_clock(attribute: &str) -> anyhow::Result<ReferenceClock> {
+    let clock = attribute
+        .strip_prefix(TS_REFCLK_PREFIX)
+        .ok_or_else(|| anyhow!("malformed ts-refclk: {attribute}"))?;
+    clock.parse()
+}
+
+fn parse_media_clock_offset(attribute: &str) -> anyhow::Result<u32> {
+    let re = Regex::new(MEDIACLK_REGEX).expect("cannot fail");
+    if let Some(caps) = re.captures(attribute) {
+        Ok(caps
+            .get(MEDIACLK_OFFSET_GROUP)
+            .expect("must exist in matches")
+            .as_str()
+            .parse()?)
+    } else {
+        Err(anyhow!("malformed mediaclk: {attribute}"))
+    }
+}
+
+impl FromStr for ReferenceClock {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(ptp) = s.strip_prefix("ptp=") else {
+            return Ok(ReferenceClock::Other(s.to_owned()));
+        };
+        let mut parts = ptp.split(':');
+        let version = parts
+            .next()
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| anyhow!("malformed PTP reference clock: {s}"))?
+            .to_owned();
+        let grandmaster = match parts.next() {
+            None | Some(PTP_TRACEABLE) => None,
+            Some(grandmaster) => Some(grandmaster.to_uppercase()),
+        };
+        let domain = parts.next().map(str::parse).transpose()?;
+        Ok(ReferenceClock::Ptp {
+            version,
+            grandmaster,
+            domain,
+        })
+    }
+}
+
+impl fmt::Display for ReferenceClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReferenceClock::Ptp {
+                version,
+                grandmaster,
+                domain,
+            } => {
+                write!(f, "ptp={version}:")?;
+                match grandmaster {
+                    Some(grandmaster) => write!(f, "{grandmaster}")?,
+                    None => write!(f, "{PTP_TRACEABLE}")?,
+                }
+                if let Some(domain) = domain {
+                    write!(f, ":{domain}")?;
+                }
+                Ok(())
+            }
+            ReferenceClock::Other(clock) => write!(f, "{clock}"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum SdpValue {
     OriginatorAndSessionIdentifier(String),          // o
@@ -179,8 +277,7 @@ fn parse_line(line: &str) -> anyhow::Result<Option<(&str, SdpValue)>> {
         return Ok(None);
     }
 
-    let mut kv = trim.split("=");
-    if let (Some(key), Some(value)) = (kv.next(), kv.next()) {
+    if let Some((key, value)) = trim.split_once('=') {
         if let Some(value) = parse_value(key, value)? {
             Ok(Some((key, value)))
         } else {
@@ -217,13 +314,18 @@ impl FromStr for SessionDescriptor {
         let mut multicast_address = None;
         let mut multicast_port = None;
         let mut packet_time = None;
+        let mut frames_per_packet = None;
+        let mut max_packet_time = None;
         let mut sample_rate = None;
+        let mut session_name = None;
+        let mut reference_clock = None;
+        let mut media_clock_offset = None;
 
         for line in lines {
             if let Some((_, value)) = parse_line(line)? {
                 match value {
                     SdpValue::OriginatorAndSessionIdentifier(_) => {}
-                    SdpValue::SessionName(_) => {}
+                    SdpValue::SessionName(name) => session_name = Some(name),
                     SdpValue::ActiveTime(_) => {}
                     SdpValue::MediaNameAndTransportAddress(m) => {
                         multicast_port = Some(m.port);
@@ -239,9 +341,21 @@ impl FromStr for SessionDescriptor {
                             channels = Some(rtpmap.channels);
                             bit_depth = Some(rtpmap.bit_depth);
                         }
-                        if let Ok(ptime) = parse_packet_time(&a) {
+                        if let Ok(ptime) = parse_packet_time(&a, PTIME_REGEX) {
                             packet_time = Some(ptime);
                         }
+                        if let Ok(maxptime) = parse_packet_time(&a, MAXPTIME_REGEX) {
+                            max_packet_time = Some(maxptime);
+                        }
+                        if let Ok(frames) = parse_frame_count(&a) {
+                            frames_per_packet = Some(frames);
+                        }
+                        if let Ok(clock) = parse_reference_clock(&a) {
+                            reference_clock = Some(clock);
+                        }
+                        if let Ok(offset) = parse_media_clock_offset(&a) {
+                            media_clock_offset = Some(offset);
+                        }
                     }
                 }
             }
@@ -268,7 +382,12 @@ impl FromStr for SessionDescriptor {
                 multicast_address,
                 multicast_port,
                 packet_time,
+                frames_per_packet,
+                max_packet_time,
                 sample_rate,
+                session_name,
+                reference_clock,
+                media_clock_offset,
             })
         } else {
             Err(anyhow!("malformed SDP: {s}"))
@@ -276,9 +395,45 @@ impl FromStr for SessionDescriptor {
     }
 }
 
+impl SessionDescriptor {
+    /// Generates an SDP describing a stream of this session sent from the given address.
+    pub fn to_sdp(&self, source_address: Ipv4Addr, session_id: u64) -> String {
+        let name = self.session_name.as_deref().unwrap_or(DEFAULT_SESSION_NAME);
+        let mut sdp = String::new();
+        writeln!(sdp, "v=0").ok();
+        writeln!(sdp, "o=- {session_id} {session_id} IN IP4 {source_address}").ok();
+        writeln!(sdp, "s={name}").ok();
+        writeln!(sdp, "c=IN IP4 {}/32", self.multicast_address).ok();
+        writeln!(sdp, "t=0 0").ok();
+        writeln!(sdp, "m=audio {} RTP/AVP {PAYLOAD_ID}", self.multicast_port).ok();
+        writeln!(
+            sdp,
+            "a=rtpmap:{PAYLOAD_ID} {}/{}/{}",
+            self.bit_depth, self.sample_rate, self.channels
+        )
+        .ok();
+        writeln!(sdp, "a=ptime:{}", self.packet_time).ok();
+        if let Some(frames) = self.frames_per_packet {
+            writeln!(sdp, "a=framecount:{frames}").ok();
+        }
+        if let Some(max_packet_time) = self.max_packet_time {
+            writeln!(sdp, "a=maxptime:{max_packet_time}").ok();
+        }
+        if let Some(clock) = &self.reference_clock {
+            writeln!(sdp, "a={TS_REFCLK_PREFIX}{clock}").ok();
+        }
+        if let Some(offset) = self.media_clock_offset {
+            writeln!(sdp, "a=mediaclk:direct={offset}").ok();
+        }
+        writeln!(sdp, "a=recvonly").ok();
+        sdp
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::PayloadSizeError;
 
     #[test]
     fn parse_comment() {
@@ -335,4 +490,129 @@ mod test {
             }
         );
     }
+
+    #[test]
+    fn parse_encoding_names() {
+        for (name, bit_depth) in [
+            ("L8", BitDepth::L8),
+            ("L20", BitDepth::L20),
+            ("L24", BitDepth::L24),
+            ("AM824", BitDepth::AM824),
+            ("F32", BitDepth::F32),
+            ("FLOAT32", BitDepth::F32),
+            ("F32LE", BitDepth::F32LE),
+            ("F64", BitDepth::F64),
+        ] {
+            let rtp_map: RtpMap = format!("rtpmap:98 {name}/48000/2").parse().unwrap();
+            assert_eq!(rtp_map.bit_depth, bit_depth);
+            assert_eq!(
+                bit_depth.to_string().parse::<BitDepth>().unwrap(),
+                bit_depth
+            );
+        }
+        assert!("L16X".parse::<BitDepth>().is_err());
+        assert!("FLOAT".parse::<BitDepth>().is_err());
+    }
+
+    #[test]
+    fn generated_sdp_roundtrip() {
+        let sd = SessionDescriptor {
+            multicast_address: Ipv4Addr::new(239, 69, 1, 2),
+            multicast_port: 5006,
+            bit_depth: BitDepth::L24,
+            channels: 2,
+            sample_rate: 48000,
+            packet_time: 0.333,
+            frames_per_packet: Some(16),
+            max_packet_time: Some(1.0),
+            session_name: Some("Talkback".to_owned()),
+            reference_clock: Some(ReferenceClock::Ptp {
+                version: "IEEE1588-2008".to_owned(),
+                grandmaster: Some("39-A7-94-FF-FE-07-CB-D0".to_owned()),
+                domain: Some(0),
+            }),
+            media_clock_offset: Some(963214424),
+        };
+        let sdp = sd.to_sdp(Ipv4Addr::new(192, 168, 1, 10), 42);
+        assert!(sdp.contains("o=- 42 42 IN IP4 192.168.1.10\n"));
+        assert_eq!(sdp.parse::<SessionDescriptor>().unwrap(), sd);
+    }
+
+    #[test]
+    fn parse_packet_times() {
+        let sdp = "v=0
+o=- 1 1 IN IP4 192.168.1.20
+s=Low Latency
+c=IN IP4 239.69.3.5/32
+t=0 0
+m=audio 5004 RTP/AVP 97
+a=rtpmap:97 L24/48000/8
+a=ptime:0.333
+a=maxptime:1
+";
+        let sd: SessionDescriptor = sdp.parse().unwrap();
+        assert_eq!(sd.packet_time, 0.333);
+        assert_eq!(sd.max_packet_time, Some(1.0));
+        assert_eq!(sd.buffer_size_frames(), 16);
+        assert_eq!(sd.buffer_size_bytes(), 16 * 8 * 3);
+        assert_eq!(sd.validate_payload_size(48 * 8 * 3), Ok(()));
+        assert_eq!(
+            sd.validate_payload_size(49 * 8 * 3),
+            Err(PayloadSizeError::UnexpectedLength {
+                expected: 384,
+                len: 49 * 8 * 3
+            })
+        );
+        assert_eq!(
+            sd.validate_payload_size(383),
+            Err(PayloadSizeError::PartialFrame {
+                expected: 384,
+                len: 383
+            })
+        );
+
+        let sd: SessionDescriptor = sdp.replace("a=maxptime:1\n", "").parse().unwrap();
+        assert!(matches!(
+            sd.validate_payload_size(8 * 8 * 3),
+            Err(PayloadSizeError::UnexpectedLength { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_media_clock() {
+        let sdp = "v=0
+o=- 1 1 IN IP4 192.168.1.20
+s=Stage Box
+c=IN IP4 239.69.3.4/32
+t=0 0
+a=ts-refclk:ptp=IEEE1588-2008:00-1d-c1-ff-fe-0e-10-3c:0
+a=mediaclk:direct=0
+m=audio 5004 RTP/AVP 97
+a=rtpmap:97 L24/48000/8
+a=ptime:1
+a=ts-refclk:ptp=IEEE1588-2008:traceable
+a=mediaclk:direct=1550281721 rate=48000/1
+";
+        let sd: SessionDescriptor = sdp.parse().unwrap();
+        assert_eq!(
+            sd.reference_clock,
+            Some(ReferenceClock::Ptp {
+                version: "IEEE1588-2008".to_owned(),
+                grandmaster: None,
+                domain: None
+            })
+        );
+        assert_eq!(sd.media_clock_offset, Some(1550281721));
+
+        let (_, value) = parse_line("a=mediaclk:direct=0").unwrap().unwrap();
+        assert_eq!(value, SdpValue::Attribute("mediaclk:direct=0".to_owned()));
+
+        let clock: ReferenceClock = "ptp=IEEE1588-2008:00-1d-c1-ff-fe-0e-10-3c:0"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            clock.to_string(),
+            "ptp=IEEE1588-2008:00-1D-C1-FF-FE-0E-10-3C:0"
+        );
+    }
 }
@@ -1,8 +1,23 @@
-use crate::SessionDescriptor;
+use crate::{
+    drift::DriftEstimator,
+    generator::{Generator, GeneratorSession},
+    pcap::{self, CaptureReader},
+    rtcp::{self, ReportBlock, RtcpPacket, SenderReport},
+    watchdog::Watchdog,
+    PayloadSizeError, SessionDescriptor,
+};
 use anyhow::anyhow;
 use rtp_rs::RtpReader;
+use serde::{Deserialize, Serialize};
 use socket2::{Domain, Socket, Type};
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::{
+    fs::File,
+    io::BufReader,
+    net::{Ipv4Addr, SocketAddrV4},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tokio::{
     net::UdpSocket,
     select, spawn,
@@ -10,68 +25,335 @@ use tokio::{
         broadcast,
         mpsc::{self},
     },
-    time::Instant,
+    task::{spawn_blocking, JoinHandle},
+    time::{interval, sleep_until, Instant},
 };
 
+const REPLAY_BUFFER_PACKETS: usize = 1024;
+const RECEIVER_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+const RTCP_CNAME: &str = "aes67-to-ws";
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamStats {
+    pub ssrc: Option<u32>,
+    pub packets_received: u64,
+    pub packets_lost: u64,
+    /// Highest sequence number received, extended by the number of sequence number cycles.
+    pub extended_highest_sequence_number: u32,
+    /// Interarrival jitter in RTP timestamp units.
+    pub jitter: f64,
+    /// Estimated rate of the sender's media clock relative to the local clock in ppm, positive if
+    /// the sender runs fast.
+    pub drift_ppm: Option<f64>,
+    /// CNAME of the sender as announced via RTCP SDES.
+    pub cname: Option<String>,
+    /// The most recent RTCP sender report.
+    pub sender_report: Option<SenderReport>,
+    /// Packets whose payload size did not match the session description.
+    pub payload_size_errors: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    /// The sender left the session with an RTCP BYE.
+    Goodbye { reason: Option<String> },
+    /// Received payloads do not match the session's packet size. Sent when the error differs
+    /// from the previous packet's. Payloads that do not hold whole frames are dropped.
+    PayloadSizeMismatch(PayloadSizeError),
+}
+
+/// Audio payload of a received RTP packet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub payload: Vec<u8>,
+    pub timestamp: u32,
+    /// Media time of the first sample in nanoseconds since the media clock epoch (the PTP epoch
+    /// for AES67), if the session announces its media clock offset.
+    pub media_time: Option<u64>,
+}
+
+struct RtpPacket {
+    payload: Vec<u8>,
+    sequence_number: u16,
+    timestamp: u32,
+    ssrc: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReplaySpeed {
+    #[default]
+    Realtime,
+    AsFastAsPossible,
+}
+
+/// Replays the RTP packets of a capture file that were sent to a session's multicast group.
+pub struct Replay {
+    packets: mpsc::Receiver<(Duration, Vec<u8>)>,
+    speed: ReplaySpeed,
+    start: Option<(Instant, Duration)>,
+    /// Packet waiting for its time to be replayed, kept if the receive is cancelled meanwhile.
+    pending: Option<(Duration, Vec<u8>)>,
+    exhausted: bool,
+}
+
+pub enum Source {
+    Multicast(UdpSocket),
+    Replay(Replay),
+    Generator(Box<Generator>),
+}
+
+impl Source {
+    async fn recv(&mut self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        match self {
+            Source::Multicast(socket) => Ok(socket.recv(buf).await?),
+            Source::Replay(replay) => {
+                if replay.pending.is_none() {
+                    let Some(packet) = replay.packets.recv().await else {
+                        replay.exhausted = true;
+                        return Ok(0);
+                    };
+                    replay.pending = Some(packet);
+                }
+                if let (ReplaySpeed::Realtime, Some((timestamp, _))) =
+                    (&replay.speed, &replay.pending)
+                {
+                    let (start, first_timestamp) =
+                        *replay.start.get_or_insert((Instant::now(), *timestamp));
+                    sleep_until(start + timestamp.saturating_sub(first_timestamp)).await;
+                }
+                let (_, packet) = replay.pending.take().expect("replayed packet pending");
+                let len = packet.len().min(buf.len());
+                buf[..len].copy_from_slice(&packet[..len]);
+                Ok(len)
+            }
+            Source::Generator(generator) => {
+                let packet = generator.next_packet().await?;
+                let len = packet.len().min(buf.len());
+                buf[..len].copy_from_slice(&packet[..len]);
+                Ok(len)
+            }
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        match self {
+            Source::Multicast(_) | Source::Generator(_) => false,
+            Source::Replay(replay) => replay.exhausted,
+        }
+    }
+}
+
 pub struct Stream {
     pub descriptor: SessionDescriptor,
-    pub socket: Option<UdpSocket>,
+    pub source: Option<Source>,
+    pub stats: Arc<Mutex<StreamStats>>,
+    pub events: broadcast::Sender<StreamEvent>,
+    /// Send RTCP receiver reports to the session's RTCP port while playing.
+    pub receiver_reports: bool,
+    /// Raises alarms for interruptions, silence and clipping while playing.
+    pub watchdog: Option<Watchdog>,
+    rtcp: Option<UdpSocket>,
 }
 
 impl Stream {
     pub async fn new(
         descriptor: SessionDescriptor,
         local_address: Ipv4Addr,
+        receive_buffer: Option<usize>,
     ) -> anyhow::Result<Self> {
         let addr = SocketAddrV4::new(descriptor.multicast_address, descriptor.multicast_port);
         let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
         socket.join_multicast_v4(&descriptor.multicast_address, &local_address)?;
         socket.set_reuse_address(true)?;
+        if let Some(size) = receive_buffer {
+            socket.set_recv_buffer_size(size)?;
+        }
         socket.bind(&addr.into())?;
         socket.set_nonblocking(true)?;
         let socket = UdpSocket::from_std(socket.into())?;
 
-        Ok(Stream {
+        let rtcp = match open_rtcp_socket(&descriptor, local_address) {
+            Ok(rtcp) => Some(rtcp),
+            Err(e) => {
+                log::warn!("Could not open RTCP socket, RTCP will be ignored: {e}");
+                None
+            }
+        };
+
+        let mut stream = Stream::with_source(descriptor, Source::Multicast(socket));
+        stream.rtcp = rtcp;
+        Ok(stream)
+    }
+
+    fn with_source(descriptor: SessionDescriptor, source: Source) -> Self {
+        Stream {
             descriptor,
-            socket: Some(socket),
-        })
+            source: Some(source),
+            stats: Default::default(),
+            events: broadcast::channel(16).0,
+            receiver_reports: false,
+            watchdog: None,
+            rtcp: None,
+        }
+    }
+
+    /// Creates a stream from the packets in a pcap or pcapng file that match the descriptor's
+    /// multicast group and port.
+    pub fn from_capture(
+        descriptor: SessionDescriptor,
+        path: &Path,
+        speed: ReplaySpeed,
+    ) -> anyhow::Result<Self> {
+        let destination =
+            SocketAddrV4::new(descriptor.multicast_address, descriptor.multicast_port);
+        let mut reader = CaptureReader::new(BufReader::new(File::open(path)?))?;
+        let (tx, packets) = mpsc::channel(REPLAY_BUFFER_PACKETS);
+
+        log::info!("Replaying {destination} from {}", path.display());
+        spawn_blocking(move || loop {
+            match reader.next_frame() {
+                Ok(Some(frame)) => {
+                    if let Some(payload) = pcap::udp_payload(&frame, destination) {
+                        if tx
+                            .blocking_send((frame.timestamp, payload.to_owned()))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!("Error reading capture file: {e}");
+                    break;
+                }
+            }
+        });
+
+        Ok(Stream::with_source(
+            descriptor,
+            Source::Replay(Replay {
+                packets,
+                speed,
+                start: None,
+                pending: None,
+                exhausted: false,
+            }),
+        ))
     }
 
+    /// Creates a stream of a synthetic test signal in the session's format.
+    pub fn from_generator(session: GeneratorSession) -> anyhow::Result<Self> {
+        let descriptor = session.descriptor.clone();
+        let generator = Generator::new(session)?;
+        log::info!("Generating test signal {descriptor:?}");
+        Ok(Stream::with_source(
+            descriptor,
+            Source::Generator(Box::new(generator)),
+        ))
+    }
+
+    /// Starts forwarding the received packets until the returned handle stops the stream or is
+    /// dropped, the receiver of `tx` is closed or the source is exhausted.
     pub async fn play(
         &mut self,
-        tx: mpsc::UnboundedSender<Vec<u8>>,
-        stop: broadcast::Sender<()>,
-    ) -> anyhow::Result<()> {
+        tx: mpsc::UnboundedSender<Packet>,
+    ) -> anyhow::Result<StreamHandle> {
         let mut buf = [0; 102400];
+        let (stop, _) = broadcast::channel(1);
 
         let mut start = Instant::now();
         let mut counter = 0;
 
-        let socket = self
-            .socket
+        let mut source = self
+            .source
             .take()
             .ok_or(anyhow!("receiver already started"))?;
 
+        if let Some(rtcp) = self.rtcp.take() {
+            spawn(receive_rtcp(
+                rtcp,
+                self.descriptor.clone(),
+                self.stats.clone(),
+                self.events.clone(),
+                stop.clone(),
+                self.receiver_reports,
+            ));
+        }
+
+        let stats = self.stats.clone();
+        let sample_rate = self.descriptor.sample_rate as f64;
+        let mut drift = DriftEstimator::new(self.descriptor.sample_rate);
+        let drift_origin = Instant::now();
+        let media_clock = self
+            .descriptor
+            .media_clock_offset
+            .map(|offset| (offset, self.descriptor.sample_rate));
+        let handle_stop = stop.clone();
         let mut stop = stop.subscribe();
+        let mut watchdog = self.watchdog.take();
+        let mut watchdog_checks = interval(WATCHDOG_INTERVAL);
+        let descriptor = self.descriptor.clone();
+        let events = self.events.clone();
 
-        spawn(async move {
-            let mut previous_sequence_number = None;
+        let receiver = spawn(async move {
+            let mut previous: Option<(u16, u32, Instant)> = None;
+            let mut sequence_cycles = 0u32;
+            let mut size_error = None;
             loop {
                 select! {
                     _ = stop.recv() => { break; },
-                    recv = receive_rtp_payload(&socket, &mut buf) => {
+                    _ = watchdog_checks.tick(), if watchdog.is_some() => {
+                        if let Some(watchdog) = &mut watchdog {
+                            watchdog.check(Instant::now().into_std());
+                        }
+                    }
+                    recv = receive_rtp_payload(&mut source, &mut buf) => {
                         match recv {
-                            Ok(Some((payload,sequence_number))) => {
+                            Ok(Some(packet)) => {
+                                let RtpPacket { payload, sequence_number, timestamp, ssrc } = packet;
+                                let arrival = Instant::now();
+                                let valid_size = descriptor.validate_payload_size(payload.len());
+                                let forward = !matches!(valid_size, Err(PayloadSizeError::PartialFrame { .. }));
+                                if let Some(watchdog) = watchdog.as_mut().filter(|_| forward) {
+                                    watchdog.packet(&payload, arrival.into_std());
+                                }
 
-                                if let Some(previous_sequence_number) = previous_sequence_number {
-                                    let diff = sequence_number - previous_sequence_number;
-                                    if diff < 1 && !(sequence_number == 0 && previous_sequence_number == 65535) {
+                                let mut stats = stats.lock().expect("mutex poisoned");
+                                stats.ssrc = Some(ssrc);
+                                stats.packets_received += 1;
+                                if valid_size.is_err() {
+                                    stats.payload_size_errors += 1;
+                                }
+                                let error = valid_size.err();
+                                if let Some(error) = error.as_ref().filter(|e| size_error.as_ref() != Some(*e)) {
+                                    log::warn!("{error}");
+                                    events.send(StreamEvent::PayloadSizeMismatch(error.clone())).ok();
+                                }
+                                size_error = error;
+                                if let Some((previous_sequence_number, previous_timestamp, previous_arrival)) = previous {
+                                    let diff = sequence_number.wrapping_sub(previous_sequence_number);
+                                    if diff == 0 || diff > u16::MAX / 2 {
                                         log::warn!("Inconsistent RTP sequence number '{sequence_number}', previous was {previous_sequence_number}")
                                     } else if diff > 1 {
                                         log::warn!("Detected packet loss, {} packet(s) were not received", diff-1);
+                                        stats.packets_lost += diff as u64 - 1;
+                                    }
+                                    if sequence_number < previous_sequence_number && diff <= u16::MAX / 2 {
+                                        sequence_cycles = sequence_cycles.wrapping_add(1);
                                     }
+
+                                    let transit = (arrival - previous_arrival).as_secs_f64() * sample_rate
+                                        - timestamp.wrapping_sub(previous_timestamp) as i32 as f64;
+                                    stats.jitter += (transit.abs() - stats.jitter) / 16.0;
                                 }
-                                previous_sequence_number = Some(sequence_number);
+                                stats.extended_highest_sequence_number = sequence_cycles << 16 | sequence_number as u32;
+                                stats.drift_ppm = drift.update(arrival - drift_origin, timestamp);
+                                drop(stats);
+                                previous = Some((sequence_number, timestamp, arrival));
 
                                 if start.elapsed().as_secs_f32() >= 1.0 {
                                     log::debug!(
@@ -84,13 +366,25 @@ impl Stream {
                                 } else {
                                     counter += 1;
                                 }
-                                if let Err(e) = tx.send(payload) {
+                                let media_time = media_clock.map(|(offset, sample_rate)| {
+                                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                                    media_time(timestamp, offset, sample_rate, now)
+                                });
+                                if !forward {
+                                    continue;
+                                }
+                                if let Err(e) = tx.send(Packet { payload, timestamp, media_time }) {
                                     log::error!("Error forwarding received data: {e}");
                                     log::warn!("Stopping receiver.");
                                     break;
                                 }
                             }
-                            Ok(None) => (),
+                            Ok(None) => {
+                                if source.exhausted() {
+                                    log::info!("End of source reached.");
+                                    break;
+                                }
+                            }
                             Err(e) => {
                                 log::error!("Error receiving data: {e}");
                                 log::warn!("Stopping receiver.");
@@ -103,22 +397,254 @@ impl Stream {
             log::info!("Receiver closed.");
         });
 
-        Ok(())
+        Ok(StreamHandle {
+            stop: handle_stop,
+            receiver,
+        })
+    }
+}
+
+/// Controls a playing stream, stopping it when dropped.
+pub struct StreamHandle {
+    stop: broadcast::Sender<()>,
+    receiver: JoinHandle<()>,
+}
+
+impl StreamHandle {
+    /// Signals the stream to stop without waiting for it.
+    pub fn stop(&self) {
+        self.stop.send(()).ok();
+    }
+
+    /// Stops the stream and waits until its receiver has exited, after which no more packets
+    /// are forwarded.
+    pub async fn join(&mut self) {
+        self.stop();
+        (&mut self.receiver).await.ok();
+    }
+
+    /// Cancels the receiver at its next await point and waits until it is gone.
+    pub async fn abort(&mut self) {
+        self.stop();
+        self.receiver.abort();
+        (&mut self.receiver).await.ok();
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        self.stop();
     }
 }
 
 async fn receive_rtp_payload(
-    sock: &UdpSocket,
+    source: &mut Source,
     buf: &mut [u8],
-) -> anyhow::Result<Option<(Vec<u8>, i32)>> {
-    let len = sock.recv(buf).await?;
+) -> anyhow::Result<Option<RtpPacket>> {
+    let len = source.recv(buf).await?;
     if len > 0 {
         let rtp = RtpReader::new(&buf[0..len]).map_err(|e| anyhow!("{e:?}"))?;
         let end = rtp.payload().len() - rtp.padding().unwrap_or(0) as usize;
-        let data = (&rtp.payload()[0..end]).to_owned();
-        let sequence_number: u16 = rtp.sequence_number().into();
-        Ok(Some((data, sequence_number as i32)))
+        Ok(Some(RtpPacket {
+            payload: rtp.payload()[0..end].to_owned(),
+            sequence_number: rtp.sequence_number().into(),
+            timestamp: rtp.timestamp(),
+            ssrc: rtp.ssrc(),
+        }))
     } else {
         Ok(None)
     }
 }
+
+/// Computes the media time in nanoseconds of an RTP timestamp, resolving the timestamp's
+/// wrap-arounds using the local clock, which is expected to be PTP disciplined.
+fn media_time(timestamp: u32, offset: u32, sample_rate: u32, now: Duration) -> u64 {
+    let sample_rate = sample_rate.max(1) as u128;
+    let now = now.as_nanos() * sample_rate / 1_000_000_000;
+    let delta = (timestamp.wrapping_sub(offset)).wrapping_sub(now as u32) as i32;
+    let samples = (now as i128 + delta as i128).max(0) as u128;
+    (samples * 1_000_000_000 / sample_rate) as u64
+}
+
+fn open_rtcp_socket(
+    descriptor: &SessionDescriptor,
+    local_address: Ipv4Addr,
+) -> anyhow::Result<UdpSocket> {
+    let addr = SocketAddrV4::new(
+        descriptor.multicast_address,
+        descriptor.multicast_port.wrapping_add(1),
+    );
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
+    socket.join_multicast_v4(&descriptor.multicast_address, &local_address)?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Handles the RTCP packets of a session, recording sender reports and source descriptions in
+/// the stream stats and stopping the stream when the sender says goodbye. Optionally sends
+/// receiver reports back to the session's RTCP port.
+async fn receive_rtcp(
+    socket: UdpSocket,
+    descriptor: SessionDescriptor,
+    stats: Arc<Mutex<StreamStats>>,
+    events: broadcast::Sender<StreamEvent>,
+    stop_tx: broadcast::Sender<()>,
+    receiver_reports: bool,
+) {
+    let mut stop = stop_tx.subscribe();
+    let mut buf = [0; 2048];
+    let destination = SocketAddrV4::new(
+        descriptor.multicast_address,
+        descriptor.multicast_port.wrapping_add(1),
+    );
+
+    let report_socket = if receiver_reports {
+        match UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)).await {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                log::warn!("Could not open socket for RTCP receiver reports: {e}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let ssrc = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let mut last_sender_report: Option<(u32, Instant)> = None;
+    let mut last_counts = (0, 0);
+    let mut reports = interval(RECEIVER_REPORT_INTERVAL);
+
+    loop {
+        select! {
+            _ = stop.recv() => break,
+            _ = reports.tick(), if report_socket.is_some() => {
+                let block = {
+                    let stats = stats.lock().expect("mutex poisoned");
+                    stats.ssrc.map(|source_ssrc| {
+                        let received = stats.packets_received - last_counts.0;
+                        let lost = stats.packets_lost - last_counts.1;
+                        last_counts = (stats.packets_received, stats.packets_lost);
+                        let (last_sender_report, delay) = last_sender_report
+                            .map(|(lsr, at)| (lsr, (at.elapsed().as_secs_f64() * 65_536.0) as u32))
+                            .unwrap_or_default();
+                        ReportBlock {
+                            ssrc: source_ssrc,
+                            fraction_lost: (lost * 256 / (received + lost).max(1)).min(255) as u8,
+                            cumulative_lost: stats.packets_lost as u32,
+                            extended_highest_sequence_number: stats.extended_highest_sequence_number,
+                            jitter: stats.jitter as u32,
+                            last_sender_report,
+                            delay_since_last_sender_report: delay,
+                        }
+                    })
+                };
+                let packet = rtcp::receiver_report(ssrc, RTCP_CNAME, block.as_ref());
+                if let Some(socket) = &report_socket {
+                    if let Err(e) = socket.send_to(&packet, destination).await {
+                        log::warn!("Could not send RTCP receiver report: {e}");
+                    }
+                }
+            }
+            recv = socket.recv(&mut buf) => {
+                let len = match recv {
+                    Ok(len) => len,
+                    Err(e) => {
+                        log::error!("Error receiving RTCP: {e}");
+                        break;
+                    }
+                };
+                let packets = match rtcp::parse(&buf[..len]) {
+                    Ok(packets) => packets,
+                    Err(e) => {
+                        log::debug!("Ignoring malformed RTCP packet: {e}");
+                        continue;
+                    }
+                };
+                for packet in packets {
+                    match packet {
+                        RtcpPacket::SenderReport(sr) => {
+                            last_sender_report = Some(((sr.ntp_timestamp >> 16) as u32, Instant::now()));
+                            stats.lock().expect("mutex poisoned").sender_report = Some(sr);
+                        }
+                        RtcpPacket::SourceDescription { cname: Some(cname), .. } => {
+                            stats.lock().expect("mutex poisoned").cname = Some(cname);
+                        }
+                        RtcpPacket::Goodbye { ssrcs, reason } => {
+                            let sender = stats.lock().expect("mutex poisoned").ssrc;
+                            // until the sender is known, a BYE may come from any other sender on the group
+                            if sender.is_some_and(|ssrc| ssrcs.contains(&ssrc)) {
+                                log::info!("Sender left the session: {}", reason.as_deref().unwrap_or("no reason given"));
+                                events.send(StreamEvent::Goodbye { reason }).ok();
+                                stop_tx.send(()).ok();
+                                return;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cancelled_replay_keeps_packet() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (tx, packets) = mpsc::channel(2);
+            tx.send((Duration::ZERO, vec![1])).await.unwrap();
+            tx.send((Duration::from_millis(50), vec![2, 2]))
+                .await
+                .unwrap();
+            drop(tx);
+            let mut source = Source::Replay(Replay {
+                packets,
+                speed: ReplaySpeed::Realtime,
+                start: None,
+                pending: None,
+                exhausted: false,
+            });
+            let mut buf = [0; 16];
+            assert_eq!(source.recv(&mut buf).await.unwrap(), 1);
+            // like a watchdog tick winning the select while the second packet is due
+            let cancelled = tokio::time::timeout(Duration::from_millis(10), source.recv(&mut buf));
+            assert!(cancelled.await.is_err());
+            assert_eq!(source.recv(&mut buf).await.unwrap(), 2);
+            assert_eq!(source.recv(&mut buf).await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn media_time_unwraps_timestamp() {
+        let now = Duration::from_secs(1_700_000_000);
+        let samples = 1_700_000_000u64 * 48_000;
+        // a packet sent 10 ms ago, with the RTP timestamp wrapped many times since the epoch
+        let timestamp = (samples - 480) as u32;
+        assert_eq!(
+            media_time(timestamp, 0, 48_000, now),
+            1_700_000_000_000_000_000 - 10_000_000
+        );
+        // the offset is removed before resolving the wrap-arounds
+        assert_eq!(
+            media_time(timestamp.wrapping_add(1234), 1234, 48_000, now),
+            1_700_000_000_000_000_000 - 10_000_000
+        );
+        // timestamps slightly ahead of the local clock are not mistaken for a wrap-around
+        assert_eq!(
+            media_time((samples + 48) as u32, 0, 48_000, now),
+            1_700_000_000_000_000_000 + 1_000_000
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{anyhow, Context};
+use futures_util::{stream, Stream, StreamExt};
+use poem::listener::{RustlsCertificate, RustlsConfig};
+use rustls::{sign, PrivateKey};
+use rustls_pemfile::Item;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::time::{interval, Interval, MissedTickBehavior};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain.
+    pub certificate: PathBuf,
+    /// PEM encoded private key.
+    pub key: PathBuf,
+    /// Serve TLS on this port in addition to plain connections on the main port. Without it, the
+    /// main port only accepts TLS.
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Seconds between checks of the certificate and key files for changes.
+    #[serde(default = "default_reload_interval")]
+    pub reload_interval: u64,
+}
+
+pub(crate) fn default_reload_interval() -> u64 {
+    10
+}
+
+impl TlsConfig {
+    fn load(&self) -> anyhow::Result<RustlsConfig> {
+        let certificate = fs::read(&self.certificate).with_context(|| {
+            format!("cannot read TLS certificate {}", self.certificate.display())
+        })?;
+        let key = fs::read(&self.key)
+            .with_context(|| format!("cannot read TLS key {}", self.key.display()))?;
+        // the listener only reports invalid files when the first client connects
+        validate(&certificate, &key)?;
+        Ok(RustlsConfig::new().fallback(RustlsCertificate::new().cert(certificate).key(key)))
+    }
+
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        Some((modified(&self.certificate)?, modified(&self.key)?))
+    }
+
+    /// Loads the certificate and key, failing if they are invalid, and returns a stream yielding
+    /// them again whenever either file changes. Changed files that are invalid are skipped.
+    pub fn watch(self) -> anyhow::Result<impl Stream<Item = RustlsConfig> + Send + 'static> {
+        let initial = self.load()?;
+        let mut ticks = interval(Duration::from_secs(self.reload_interval.max(1)));
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let modified = self.modified();
+
+        let changes = stream::unfold(
+            (self, ticks, modified),
+            |(config, mut ticks, mut modified): (TlsConfig, Interval, _)| async move {
+                loop {
+                    ticks.tick().await;
+                    let current = config.modified();
+                    if current.is_none() || current == modified {
+                        continue;
+                    }
+                    modified = current;
+                    match config.load() {
+                        Ok(tls) => {
+                            log::info!("Reloaded TLS certificate {}", config.certificate.display());
+                            return Some((tls, (config, ticks, modified)));
+                        }
+                        Err(e) => log::error!("Keeping the current TLS certificate: {e:#}"),
+                    }
+                }
+            },
+        );
+        Ok(stream::once(async { initial }).chain(changes))
+    }
+}
+
+fn validate(certificate: &[u8], key: &[u8]) -> anyhow::Result<()> {
+    let certificates =
+        rustls_pemfile::certs(&mut &*certificate).context("invalid TLS certificate")?;
+    if certificates.is_empty() {
+        return Err(anyhow!("TLS certificate file contains no certificate"));
+    }
+    // the listener only looks at the first item of the key file
+    let key = match rustls_pemfile::read_one(&mut &*key).context("invalid TLS key")? {
+        Some(Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key)) if !key.is_empty() => key,
+        _ => return Err(anyhow!("TLS key file does not start with a private key")),
+    };
+    sign::any_supported_type(&PrivateKey(key)).context("unsupported TLS key")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reject_invalid_files() {
+        let certificate = b"-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n";
+        let error = validate(b"garbage", b"garbage").unwrap_err();
+        assert!(error.to_string().contains("no certificate"), "{error:#}");
+        let error = validate(
+            certificate,
+            b"-----BEGIN EC PARAMETERS-----\nBggqhkjOPQMBBw==\n-----END EC PARAMETERS-----\n",
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("private key"), "{error:#}");
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{sap, SessionDescriptor};
+use anyhow::anyhow;
+use rtp_rs::{RtpPacketBuilder, Seq};
+use socket2::{Domain, Socket, Type};
+use std::{
+    collections::VecDeque,
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    net::UdpSocket,
+    select, spawn,
+    sync::{broadcast, mpsc},
+    time::{interval, MissedTickBehavior},
+};
+
+const PAYLOAD_TYPE: u8 = 98;
+/// Audio buffered beyond this is dropped to keep the latency of bursty clients bounded.
+pub const DEFAULT_BUFFERED_SECONDS: f64 = 0.2;
+
+/// Packetizes PCM received from a client into RTP and sends it to a multicast group, announcing
+/// the stream via SAP while it is running.
+pub struct Transmitter {
+    pub descriptor: SessionDescriptor,
+    pub sdp: String,
+    pcm: mpsc::UnboundedSender<Vec<u8>>,
+    stop: broadcast::Sender<()>,
+}
+
+impl Transmitter {
+    pub async fn start(
+        mut descriptor: SessionDescriptor,
+        local_address: Ipv4Addr,
+        buffered_seconds: f64,
+    ) -> anyhow::Result<Self> {
+        descriptor.validate()?;
+        let max_buffered = max_buffered_bytes(&descriptor, buffered_seconds);
+        if descriptor.buffer_size_bytes() as usize > max_buffered {
+            return Err(anyhow!(
+                "packets of {} bytes exceed the transmit buffer of {max_buffered} bytes",
+                descriptor.buffer_size_bytes()
+            ));
+        }
+        if !descriptor.multicast_address.is_multicast() {
+            return Err(anyhow!(
+                "not a multicast address: {}",
+                descriptor.multicast_address
+            ));
+        }
+
+        let destination =
+            SocketAddrV4::new(descriptor.multicast_address, descriptor.multicast_port);
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
+        if !local_address.is_unspecified() {
+            socket.set_multicast_if_v4(&local_address)?;
+        }
+        socket.bind(&SocketAddrV4::new(local_address, 0).into())?;
+        socket.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(socket.into())?;
+        socket.connect(destination).await?;
+
+        let origin = match socket.local_addr()?.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => local_address,
+        };
+
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let session_id = since_epoch.as_secs();
+        descriptor.media_clock_offset = Some(descriptor.media_clock_offset.unwrap_or(0));
+        let sdp = descriptor.to_sdp(origin, session_id);
+
+        let (pcm, pcm_rx) = mpsc::unbounded_channel();
+        let (stop, _) = broadcast::channel(1);
+
+        log::info!("Transmitting to {destination}");
+        spawn(transmit(
+            socket,
+            descriptor.clone(),
+            since_epoch,
+            buffered_seconds,
+            pcm_rx,
+            stop.clone(),
+        ));
+        spawn(sap::announce(
+            local_address,
+            origin,
+            sdp.clone(),
+            stop.clone(),
+        ));
+
+        Ok(Transmitter {
+            descriptor,
+            sdp,
+            pcm,
+            stop,
+        })
+    }
+
+    /// Queues interleaved PCM in the session's wire format (big endian, session bit depth) for
+    /// transmission.
+    pub fn send(&self, pcm: Vec<u8>) -> anyhow::Result<()> {
+        self.pcm
+            .send(pcm)
+            .map_err(|_| anyhow!("transmitter is closed"))
+    }
+}
+
+impl Drop for Transmitter {
+    fn drop(&mut self) {
+        self.stop.send(()).ok();
+    }
+}
+
+async fn transmit(
+    socket: UdpSocket,
+    descriptor: SessionDescriptor,
+    start: Duration,
+    buffered_seconds: f64,
+    mut pcm_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    stop: broadcast::Sender<()>,
+) {
+    let mut stop = stop.subscribe();
+
+    let frames_per_packet = descriptor.buffer_size_frames();
+    let channels = descriptor.channels as usize;
+    let bytes_per_packet = descriptor
+        .bit_depth
+        .bytes(frames_per_packet as usize * channels);
+    let max_buffered = max_buffered_bytes(&descriptor, buffered_seconds);
+    let packet_duration =
+        Duration::from_secs_f64(frames_per_packet as f64 / descriptor.sample_rate as f64);
+
+    let ssrc = start.subsec_nanos() ^ start.as_secs() as u32;
+    let mut sequence_number = start.subsec_nanos() as u16;
+    // the media clock is the system clock, which is expected to be PTP disciplined
+    let mut timestamp = ((start.as_secs_f64() * descriptor.sample_rate as f64) as u64 as u32)
+        .wrapping_add(descriptor.media_clock_offset.unwrap_or(0));
+
+    let mut buffer = VecDeque::with_capacity(max_buffered);
+    let mut ticks = interval(packet_duration);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Burst);
+
+    loop {
+        select! {
+            _ = stop.recv() => break,
+            _ = ticks.tick() => {
+                while let Ok(pcm) = pcm_rx.try_recv() {
+                    buffer.extend(pcm);
+                }
+                if buffer.len() > max_buffered {
+                    let excess = buffer.len() - max_buffered;
+                    // drop whole packets so that samples stay aligned
+                    let excess = excess.div_ceil(bytes_per_packet) * bytes_per_packet;
+                    buffer.drain(..excess.min(buffer.len()));
+                    log::warn!("Transmit buffer overflow, dropped {excess} bytes");
+                }
+
+                if buffer.len() >= bytes_per_packet {
+                    let payload: Vec<u8> = buffer.drain(..bytes_per_packet).collect();
+                    match RtpPacketBuilder::new()
+                        .payload_type(PAYLOAD_TYPE)
+                        .ssrc(ssrc)
+                        .sequence(Seq::from(sequence_number))
+                        .timestamp(timestamp)
+                        .payload(&payload)
+                        .build()
+                    {
+                        Ok(packet) => {
+                            if let Err(e) = socket.send(&packet).await {
+                                log::error!("Error sending RTP packet: {e}");
+                                break;
+                            }
+                            sequence_number = sequence_number.wrapping_add(1);
+                        }
+                        Err(e) => log::error!("Error building RTP packet: {e:?}"),
+                    }
+                }
+
+                timestamp = timestamp.wrapping_add(frames_per_packet);
+            }
+        }
+    }
+
+    log::info!("Transmitter closed.");
+}
+
+/// Bytes of PCM buffered at most before audio is dropped.
+fn max_buffered_bytes(descriptor: &SessionDescriptor, buffered_seconds: f64) -> usize {
+    let frames = (buffered_seconds * descriptor.sample_rate as f64) as usize;
+    descriptor
+        .bit_depth
+        .bytes(frames * descriptor.channels as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reject_invalid_sessions() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let valid = SessionDescriptor {
+            multicast_address: Ipv4Addr::new(239, 69, 3, 4),
+            ..Default::default()
+        };
+        for descriptor in [
+            SessionDescriptor {
+                sample_rate: 0,
+                frames_per_packet: Some(16),
+                ..valid.clone()
+            },
+            SessionDescriptor {
+                frames_per_packet: Some(48_000),
+                ..valid
+            },
+        ] {
+            let start = Transmitter::start(descriptor, Ipv4Addr::UNSPECIFIED, 0.2);
+            assert!(runtime.block_on(start).is_err());
+        }
+    }
+}
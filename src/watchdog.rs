@@ -0,0 +1,398 @@
+use crate::{pcm, SessionDescriptor};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast;
+
+/// Longest time a condition may have to persist before an alarm is raised or cleared.
+const MAX_HOLD_SECONDS: f32 = 3_600.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchdogConfig {
+    /// Raise an alarm if no packet was received for this many milliseconds.
+    #[serde(default = "default_no_packets_ms")]
+    pub no_packets_ms: u64,
+    /// Raise an alarm if a channel stayed below the silence threshold for this many seconds.
+    #[serde(default = "default_silence_seconds")]
+    pub silence_seconds: f32,
+    /// Level in dBFS below which a channel is considered silent.
+    #[serde(default = "default_silence_threshold")]
+    pub silence_threshold: f32,
+    /// Level in dBFS at or above which a sample is considered clipped.
+    #[serde(default = "default_clip_level")]
+    pub clip_level: f32,
+    /// Number of consecutive clipped samples that raise a clipping alarm.
+    #[serde(default = "default_clip_samples")]
+    pub clip_samples: usize,
+    /// Seconds without clipping after which a clipping alarm is cleared.
+    #[serde(default = "default_clip_hold_seconds")]
+    pub clip_hold_seconds: f32,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            no_packets_ms: default_no_packets_ms(),
+            silence_seconds: default_silence_seconds(),
+            silence_threshold: default_silence_threshold(),
+            clip_level: default_clip_level(),
+            clip_samples: default_clip_samples(),
+            clip_hold_seconds: default_clip_hold_seconds(),
+        }
+    }
+}
+
+impl WatchdogConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.no_packets_ms == 0 || self.no_packets_ms > MAX_HOLD_SECONDS as u64 * 1_000 {
+            return Err(anyhow!(
+                "no packets alarm delay must be above 0 and at most {MAX_HOLD_SECONDS} seconds"
+            ));
+        }
+        for seconds in [self.silence_seconds, self.clip_hold_seconds] {
+            if !(seconds > 0.0 && seconds <= MAX_HOLD_SECONDS) {
+                return Err(anyhow!(
+                    "alarm delays must be above 0 and at most {MAX_HOLD_SECONDS} seconds"
+                ));
+            }
+        }
+        if !self.silence_threshold.is_finite() || !self.clip_level.is_finite() {
+            return Err(anyhow!("alarm levels must be finite"));
+        }
+        if self.clip_samples == 0 {
+            return Err(anyhow!("clipping alarms need at least one clipped sample"));
+        }
+        Ok(())
+    }
+}
+
+fn default_no_packets_ms() -> u64 {
+    500
+}
+
+fn default_silence_seconds() -> f32 {
+    10.0
+}
+
+fn default_silence_threshold() -> f32 {
+    -90.0
+}
+
+fn default_clip_level() -> f32 {
+    -0.01
+}
+
+fn default_clip_samples() -> usize {
+    3
+}
+
+fn default_clip_hold_seconds() -> f32 {
+    2.0
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AlarmKind {
+    NoPackets,
+    Silence { channel: u16 },
+    Clipping { channel: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Alarm {
+    /// Identifies the watched stream among streams of the same session.
+    pub stream_id: u64,
+    pub session: String,
+    pub kind: AlarmKind,
+    /// Time the alarm was raised, in milliseconds since the unix epoch.
+    pub since: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlarmEvent {
+    Raised(Alarm),
+    Cleared(Alarm),
+}
+
+/// Holds the active alarms of all watched streams and notifies subscribers of changes.
+#[derive(Clone)]
+pub struct AlarmRegistry {
+    alarms: Arc<Mutex<HashMap<(u64, AlarmKind), Alarm>>>,
+    events: broadcast::Sender<AlarmEvent>,
+    next_stream_id: Arc<AtomicU64>,
+}
+
+impl Default for AlarmRegistry {
+    fn default() -> Self {
+        AlarmRegistry {
+            alarms: Default::default(),
+            events: broadcast::channel(64).0,
+            next_stream_id: Default::default(),
+        }
+    }
+}
+
+impl AlarmRegistry {
+    pub fn list(&self) -> Vec<Alarm> {
+        let mut alarms: Vec<Alarm> = self
+            .alarms
+            .lock()
+            .expect("mutex poisoned")
+            .values()
+            .cloned()
+            .collect();
+        alarms.sort_by_key(|a| (a.since, a.stream_id));
+        alarms
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AlarmEvent> {
+        self.events.subscribe()
+    }
+
+    fn raise(&self, stream_id: u64, session: &str, kind: AlarmKind) {
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let alarm = Alarm {
+            stream_id,
+            session: session.to_owned(),
+            kind: kind.clone(),
+            since,
+        };
+        log::warn!("Alarm raised for {session}: {kind:?}");
+        self.alarms
+            .lock()
+            .expect("mutex poisoned")
+            .insert((stream_id, kind), alarm.clone());
+        self.events.send(AlarmEvent::Raised(alarm)).ok();
+    }
+
+    fn clear(&self, stream_id: u64, kind: AlarmKind) {
+        let alarm = self
+            .alarms
+            .lock()
+            .expect("mutex poisoned")
+            .remove(&(stream_id, kind));
+        if let Some(alarm) = alarm {
+            log::info!("Alarm cleared for {}: {:?}", alarm.session, alarm.kind);
+            self.events.send(AlarmEvent::Cleared(alarm)).ok();
+        }
+    }
+}
+
+/// Watches the packets of a stream for interruptions, silence and clipping. All alarms raised
+/// by a watchdog are cleared when it is dropped.
+pub struct Watchdog {
+    id: u64,
+    session: String,
+    descriptor: SessionDescriptor,
+    config: WatchdogConfig,
+    registry: AlarmRegistry,
+    last_packet: Instant,
+    silent_since: Vec<Option<Instant>>,
+    clipped_samples: Vec<usize>,
+    last_clip: Vec<Option<Instant>>,
+    raised: HashSet<AlarmKind>,
+}
+
+impl Watchdog {
+    pub fn new(
+        session: String,
+        descriptor: SessionDescriptor,
+        config: WatchdogConfig,
+        registry: AlarmRegistry,
+    ) -> Self {
+        let channels = descriptor.channels as usize;
+        let now = Instant::now();
+        Watchdog {
+            id: registry.next_stream_id.fetch_add(1, Ordering::Relaxed),
+            session,
+            descriptor,
+            config,
+            registry,
+            last_packet: now,
+            silent_since: vec![Some(now); channels],
+            clipped_samples: vec![0; channels],
+            last_clip: vec![None; channels],
+            raised: HashSet::new(),
+        }
+    }
+
+    pub fn packet(&mut self, payload: &[u8], now: Instant) {
+        self.last_packet = now;
+        self.set(AlarmKind::NoPackets, false);
+
+        let channels = self.descriptor.channels as usize;
+        let silence = 10f32.powf(self.config.silence_threshold / 20.0);
+        let clip = 10f32.powf(self.config.clip_level / 20.0);
+        let samples = pcm::decode(payload, &self.descriptor.bit_depth);
+
+        let mut peaks = vec![0f32; channels];
+        for frame in samples.chunks_exact(channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                peaks[channel] = peaks[channel].max(sample.abs());
+                if sample.abs() >= clip {
+                    self.clipped_samples[channel] += 1;
+                    if self.clipped_samples[channel] >= self.config.clip_samples {
+                        self.last_clip[channel] = Some(now);
+                    }
+                } else {
+                    self.clipped_samples[channel] = 0;
+                }
+            }
+        }
+
+        for (channel, peak) in peaks.into_iter().enumerate() {
+            if peak >= silence {
+                self.silent_since[channel] = None;
+                self.set(
+                    AlarmKind::Silence {
+                        channel: channel as u16,
+                    },
+                    false,
+                );
+            } else if self.silent_since[channel].is_none() {
+                self.silent_since[channel] = Some(now);
+            }
+            if self.last_clip[channel] == Some(now) {
+                self.set(
+                    AlarmKind::Clipping {
+                        channel: channel as u16,
+                    },
+                    true,
+                );
+            }
+        }
+    }
+
+    /// Raises or clears the alarms that depend on time passing.
+    pub fn check(&mut self, now: Instant) {
+        let no_packets = Duration::from_millis(self.config.no_packets_ms);
+        self.set(
+            AlarmKind::NoPackets,
+            now.duration_since(self.last_packet) >= no_packets,
+        );
+
+        let silence = Duration::from_secs_f32(self.config.silence_seconds.max(0.0));
+        let clip_hold = Duration::from_secs_f32(self.config.clip_hold_seconds.max(0.0));
+        for channel in 0..self.descriptor.channels as usize {
+            if let Some(since) = self.silent_since[channel] {
+                if now.duration_since(since) >= silence {
+                    self.set(
+                        AlarmKind::Silence {
+                            channel: channel as u16,
+                        },
+                        true,
+                    );
+                }
+            }
+            if let Some(last_clip) = self.last_clip[channel] {
+                if now.duration_since(last_clip) >= clip_hold {
+                    self.last_clip[channel] = None;
+                    self.set(
+                        AlarmKind::Clipping {
+                            channel: channel as u16,
+                        },
+                        false,
+                    );
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, kind: AlarmKind, active: bool) {
+        if active && !self.raised.contains(&kind) {
+            self.registry.raise(self.id, &self.session, kind.clone());
+            self.raised.insert(kind);
+        } else if !active && self.raised.remove(&kind) {
+            self.registry.clear(self.id, kind);
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        for kind in self.raised.drain() {
+            self.registry.clear(self.id, kind);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BitDepth;
+
+    fn packet(left: f32, right: f32) -> Vec<u8> {
+        pcm::encode(&[left, right].repeat(48), &BitDepth::L24)
+    }
+
+    #[test]
+    fn validate_config() {
+        assert!(WatchdogConfig::default().validate().is_ok());
+        for config in [
+            WatchdogConfig {
+                silence_seconds: 1e30,
+                ..Default::default()
+            },
+            WatchdogConfig {
+                clip_hold_seconds: f32::NAN,
+                ..Default::default()
+            },
+            WatchdogConfig {
+                clip_samples: 0,
+                ..Default::default()
+            },
+        ] {
+            assert!(config.validate().is_err(), "{config:?}");
+        }
+    }
+
+    #[test]
+    fn raise_and_clear_alarms() {
+        let registry = AlarmRegistry::default();
+        let descriptor = SessionDescriptor {
+            bit_depth: BitDepth::L24,
+            ..Default::default()
+        };
+        let mut watchdog = Watchdog::new(
+            "test".to_owned(),
+            descriptor,
+            WatchdogConfig::default(),
+            registry.clone(),
+        );
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        watchdog.packet(&packet(0.5, 0.0), at(0));
+        watchdog.check(at(100));
+        assert!(registry.list().is_empty());
+
+        watchdog.check(at(600));
+        watchdog.packet(&packet(1.0, 0.0), at(10_000));
+        watchdog.check(at(10_001));
+        let kinds: Vec<AlarmKind> = registry.list().into_iter().map(|a| a.kind).collect();
+        assert_eq!(kinds.len(), 2);
+        assert!(kinds.contains(&AlarmKind::Silence { channel: 1 }));
+        assert!(kinds.contains(&AlarmKind::Clipping { channel: 0 }));
+
+        watchdog.packet(&packet(0.5, 0.5), at(13_000));
+        watchdog.check(at(13_001));
+        assert!(registry.list().is_empty());
+
+        watchdog.check(at(14_000));
+        assert_eq!(registry.list()[0].kind, AlarmKind::NoPackets);
+        drop(watchdog);
+        assert!(registry.list().is_empty());
+    }
+}